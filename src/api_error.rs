@@ -0,0 +1,301 @@
+use crate::auth::Role;
+use crate::message_transformer::FieldError;
+use crate::morse_converter::{ConversionError, UnsupportedChar};
+use crate::presets::PresetError;
+use serde_json::json;
+use std::convert::Infallible;
+use warp::http::StatusCode;
+
+/// Every error the API reports. Handlers reject with one and
+/// `handle_rejection` turns it into a `{code, message, details}` reply, so
+/// the status and code of each failure are decided here.
+#[derive(Debug)]
+pub enum ApiError {
+    /// The body is not JSON or does not fit the endpoint
+    InvalidJson(String),
+    /// The query string cannot be parsed
+    MalformedQuery(String),
+    InvalidImport(String),
+    InvalidHeader(String),
+    AuthDisabled,
+    /// No valid session
+    Unauthorized,
+    InvalidCredentials,
+    /// Logged in, but the role is too low
+    Forbidden(Role),
+    NotFound(String),
+    VersionNotFound(u64),
+    MethodNotAllowed,
+    /// The pool holds `max_messages` from limits.toml. Answered with 429 like
+    /// a rate limit, but without Retry-After: waiting does not help, only
+    /// deleting messages does.
+    MessageLimitReached(usize),
+    LengthRequired,
+    PayloadTooLarge,
+    UnsupportedMediaType,
+    /// Query parameters that parse but make no sense
+    InvalidQuery(String),
+    UnsupportedCharacters(Vec<UnsupportedChar>),
+    Conversion(ConversionError),
+    InvalidMetadata(Vec<FieldError>),
+    InvalidConfig(Vec<FieldError>),
+    InvalidTempo,
+    EmptyText,
+    EmptyMorse,
+    Preset(PresetError),
+    RateLimited {
+        retry_after_secs: u64,
+    },
+    ModerationQueueFull,
+    SaveFailed(String),
+    AuditUnavailable(String),
+    HistoryUnavailable(String),
+    /// A rejection nothing above describes
+    Internal,
+}
+
+impl warp::reject::Reject for ApiError {}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::InvalidJson(_)
+            | ApiError::MalformedQuery(_)
+            | ApiError::InvalidImport(_)
+            | ApiError::InvalidHeader(_)
+            | ApiError::AuthDisabled => StatusCode::BAD_REQUEST,
+            ApiError::Unauthorized | ApiError::InvalidCredentials => StatusCode::UNAUTHORIZED,
+            ApiError::Forbidden(_) => StatusCode::FORBIDDEN,
+            ApiError::NotFound(_) | ApiError::VersionNotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::MethodNotAllowed => StatusCode::METHOD_NOT_ALLOWED,
+            ApiError::LengthRequired => StatusCode::LENGTH_REQUIRED,
+            ApiError::PayloadTooLarge => StatusCode::PAYLOAD_TOO_LARGE,
+            ApiError::UnsupportedMediaType => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            ApiError::InvalidQuery(_)
+            | ApiError::UnsupportedCharacters(_)
+            | ApiError::Conversion(_)
+            | ApiError::InvalidMetadata(_)
+            | ApiError::InvalidConfig(_)
+            | ApiError::InvalidTempo
+            | ApiError::EmptyText
+            | ApiError::EmptyMorse => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::Preset(error) => match error {
+                PresetError::NotFound(_) | PresetError::ScheduleNotFound(_) => {
+                    StatusCode::NOT_FOUND
+                }
+                PresetError::AlreadyExists(_) | PresetError::Scheduled(_) => StatusCode::CONFLICT,
+                PresetError::InvalidName(_)
+                | PresetError::InvalidSchedule(_)
+                | PresetError::InvalidConfig(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            },
+            ApiError::MessageLimitReached(_) | ApiError::RateLimited { .. } => {
+                StatusCode::TOO_MANY_REQUESTS
+            }
+            ApiError::ModerationQueueFull => StatusCode::SERVICE_UNAVAILABLE,
+            ApiError::SaveFailed(_)
+            | ApiError::AuditUnavailable(_)
+            | ApiError::HistoryUnavailable(_)
+            | ApiError::Internal => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    /// Stable identifier clients can match on
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::InvalidJson(_) => "invalid_json",
+            ApiError::MalformedQuery(_) => "malformed_query",
+            ApiError::InvalidImport(_) => "invalid_import",
+            ApiError::InvalidHeader(_) => "invalid_header",
+            ApiError::AuthDisabled => "auth_disabled",
+            ApiError::Unauthorized => "unauthorized",
+            ApiError::InvalidCredentials => "invalid_credentials",
+            ApiError::Forbidden(_) => "forbidden",
+            ApiError::NotFound(_) => "not_found",
+            ApiError::VersionNotFound(_) => "version_not_found",
+            ApiError::MethodNotAllowed => "method_not_allowed",
+            ApiError::MessageLimitReached(_) => "message_limit_reached",
+            ApiError::LengthRequired => "length_required",
+            ApiError::PayloadTooLarge => "payload_too_large",
+            ApiError::UnsupportedMediaType => "unsupported_media_type",
+            ApiError::InvalidQuery(_) => "invalid_query",
+            ApiError::UnsupportedCharacters(_) => "unsupported_characters",
+            ApiError::Conversion(ConversionError::UnknownAlphabet(_)) => "unknown_alphabet",
+            ApiError::InvalidMetadata(_) => "invalid_metadata",
+            ApiError::InvalidConfig(_) => "invalid_config",
+            ApiError::InvalidTempo => "invalid_tempo",
+            ApiError::EmptyText => "empty_text",
+            ApiError::EmptyMorse => "empty_morse",
+            ApiError::Preset(error) => match error {
+                PresetError::NotFound(_) => "preset_not_found",
+                PresetError::AlreadyExists(_) => "preset_exists",
+                PresetError::InvalidName(_) => "invalid_preset_name",
+                PresetError::Scheduled(_) => "preset_scheduled",
+                PresetError::InvalidSchedule(_) => "invalid_schedule",
+                PresetError::ScheduleNotFound(_) => "schedule_not_found",
+                PresetError::InvalidConfig(_) => "invalid_config",
+            },
+            ApiError::RateLimited { .. } => "rate_limited",
+            ApiError::ModerationQueueFull => "moderation_queue_full",
+            ApiError::SaveFailed(_) => "save_failed",
+            ApiError::AuditUnavailable(_) => "audit_unavailable",
+            ApiError::HistoryUnavailable(_) => "history_unavailable",
+            ApiError::Internal => "internal_error",
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            ApiError::InvalidJson(reason) => format!("Request body is not valid: {reason}"),
+            ApiError::MalformedQuery(reason) => format!("Query string is not valid: {reason}"),
+            ApiError::InvalidImport(reason)
+            | ApiError::InvalidHeader(reason)
+            | ApiError::NotFound(reason)
+            | ApiError::InvalidQuery(reason) => reason.clone(),
+            ApiError::AuthDisabled => "Authentication is not configured".to_string(),
+            ApiError::Unauthorized => "Log in to use this endpoint".to_string(),
+            ApiError::InvalidCredentials => "Wrong name or password".to_string(),
+            ApiError::Forbidden(role) => format!("This endpoint requires the {role} role"),
+            ApiError::VersionNotFound(version) => format!("Config version {version} not found"),
+            ApiError::MethodNotAllowed => "Method not allowed for this endpoint".to_string(),
+            ApiError::MessageLimitReached(max_messages) => {
+                format!("The pool already holds the maximum of {max_messages} messages")
+            }
+            ApiError::LengthRequired => "A Content-Length header is required".to_string(),
+            ApiError::PayloadTooLarge => "Request body is too large".to_string(),
+            ApiError::UnsupportedMediaType => "Unsupported content type".to_string(),
+            ApiError::UnsupportedCharacters(_) => {
+                "Text contains characters that cannot be encoded as Morse code".to_string()
+            }
+            ApiError::Conversion(error) => error.to_string(),
+            ApiError::InvalidMetadata(_) => "Message metadata validation failed".to_string(),
+            ApiError::InvalidConfig(_) => "Config validation failed".to_string(),
+            ApiError::InvalidTempo => "tempo_ms must be at least 1".to_string(),
+            ApiError::EmptyText => "Message text is empty".to_string(),
+            ApiError::EmptyMorse => {
+                "Message contains nothing that can be played as Morse code".to_string()
+            }
+            ApiError::Preset(error) => error.to_string(),
+            ApiError::RateLimited { retry_after_secs } => {
+                format!("Too many requests; try again in {retry_after_secs} seconds")
+            }
+            ApiError::ModerationQueueFull => {
+                "Too many submissions are waiting for moderation; try again later".to_string()
+            }
+            ApiError::SaveFailed(reason) => format!("Failed to save messages: {reason}"),
+            ApiError::AuditUnavailable(reason) => {
+                format!("Failed to read config audit log: {reason}")
+            }
+            ApiError::HistoryUnavailable(reason) => {
+                format!("Failed to read play history: {reason}")
+            }
+            ApiError::Internal => "Internal server error".to_string(),
+        }
+    }
+
+    /// Structured data for the client to act on; null when there is none
+    pub fn details(&self) -> serde_json::Value {
+        match self {
+            ApiError::UnsupportedCharacters(unsupported) => {
+                json!({ "unsupported_chars": unsupported })
+            }
+            ApiError::InvalidMetadata(errors)
+            | ApiError::InvalidConfig(errors)
+            | ApiError::Preset(PresetError::InvalidConfig(errors)) => json!({ "errors": errors }),
+            ApiError::MessageLimitReached(max_messages) => {
+                json!({ "max_messages": max_messages })
+            }
+            ApiError::RateLimited { retry_after_secs } => {
+                json!({ "retry_after_secs": retry_after_secs })
+            }
+            _ => serde_json::Value::Null,
+        }
+    }
+
+    pub fn reply(&self) -> warp::reply::Response {
+        use warp::Reply;
+
+        let body = json!({
+            "code": self.code(),
+            "message": self.message(),
+            "details": self.details(),
+        });
+        let mut response =
+            warp::reply::with_status(warp::reply::json(&body), self.status()).into_response();
+        if let ApiError::RateLimited { retry_after_secs } = self {
+            response
+                .headers_mut()
+                .insert(warp::http::header::RETRY_AFTER, (*retry_after_secs).into());
+        }
+        response
+    }
+}
+
+/// Replies to every rejection with the JSON error body, including the ones
+/// warp raises itself for unmatched routes and unreadable requests.
+pub async fn handle_rejection(rejection: warp::Rejection) -> Result<impl warp::Reply, Infallible> {
+    if let Some(error) = rejection.find::<ApiError>() {
+        return Ok(error.reply());
+    }
+    let error = if let Some(e) = rejection.find::<warp::filters::body::BodyDeserializeError>() {
+        ApiError::InvalidJson(
+            std::error::Error::source(e).map_or_else(|| e.to_string(), |source| source.to_string()),
+        )
+    } else if let Some(e) = rejection.find::<warp::reject::InvalidQuery>() {
+        ApiError::MalformedQuery(e.to_string())
+    } else if let Some(e) = rejection.find::<warp::reject::InvalidHeader>() {
+        ApiError::InvalidHeader(e.to_string())
+    } else if rejection.find::<warp::reject::LengthRequired>().is_some() {
+        ApiError::LengthRequired
+    } else if rejection.find::<warp::reject::PayloadTooLarge>().is_some() {
+        ApiError::PayloadTooLarge
+    } else if rejection
+        .find::<warp::reject::UnsupportedMediaType>()
+        .is_some()
+    {
+        ApiError::UnsupportedMediaType
+    } else if rejection.find::<warp::reject::MethodNotAllowed>().is_some() {
+        ApiError::MethodNotAllowed
+    } else if rejection.is_not_found() {
+        ApiError::NotFound("No such endpoint".to_string())
+    } else {
+        eprintln!("Unhandled rejection: {:?}", rejection);
+        ApiError::Internal
+    };
+    Ok(error.reply())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_variants_map_to_status_code_and_details() {
+        let error = ApiError::InvalidMetadata(vec![FieldError::new("tags", "too many")]);
+        assert_eq!(error.status(), StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(error.code(), "invalid_metadata");
+        assert_eq!(error.details()["errors"][0]["field"], "tags");
+
+        let error = ApiError::NotFound("Message m1 not found".to_string());
+        assert_eq!(error.status(), StatusCode::NOT_FOUND);
+        assert!(error.details().is_null());
+
+        let response = ApiError::RateLimited {
+            retry_after_secs: 7,
+        }
+        .reply();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[warp::http::header::RETRY_AFTER], "7");
+
+        let response = ApiError::MessageLimitReached(500).reply();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert!(
+            !response
+                .headers()
+                .contains_key(warp::http::header::RETRY_AFTER)
+        );
+        assert_eq!(
+            ApiError::MessageLimitReached(500).details()["max_messages"],
+            500
+        );
+    }
+}
@@ -0,0 +1,200 @@
+use argon2::Argon2;
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use chrono::{DateTime, Duration, Utc};
+use parking_lot::RwLock;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use uuid::Uuid;
+
+pub const SESSION_COOKIE: &str = "morse_session";
+
+const DEFAULT_SESSION_HOURS: i64 = 12;
+
+/// What a user may change. Each role includes the ones before it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Role {
+    /// Manages messages
+    Curator,
+    /// Also changes the config and controls playback
+    Operator,
+}
+
+impl std::fmt::Display for Role {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Role::Curator => write!(f, "curator"),
+            Role::Operator => write!(f, "operator"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct User {
+    pub name: String,
+    pub role: Role,
+    /// PHC string printed by `morse-code-converter hash-password`
+    pub password_hash: String,
+}
+
+/// Layout of `auth.toml`
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct AuthConfig {
+    #[serde(default)]
+    pub users: Vec<User>,
+    /// Origins of other sites allowed to call the API, or `"*"` for any.
+    /// Empty allows only the service's own pages.
+    #[serde(default)]
+    pub cors_origins: Vec<String>,
+    #[serde(default)]
+    pub session_hours: Option<i64>,
+}
+
+/// Who made a request
+#[derive(Debug, Clone, Serialize)]
+pub struct Principal {
+    pub name: String,
+    pub role: Role,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Session {
+    #[serde(flatten)]
+    pub principal: Principal,
+    pub expires_at: DateTime<Utc>,
+}
+
+/// Users from `auth.toml` and the sessions of those logged in. Without the
+/// file, authentication is disabled and every request is allowed.
+pub struct Auth {
+    config: Option<AuthConfig>,
+    sessions: RwLock<HashMap<String, Session>>,
+}
+
+impl Auth {
+    /// Reads `file_path`. A missing file disables authentication; a file that
+    /// cannot be parsed is an error, so a typo never leaves the API open.
+    pub fn load(file_path: &str) -> Result<Self, String> {
+        let config = match fs::read_to_string(file_path) {
+            Ok(content) => {
+                let config: AuthConfig = toml::from_str(&content)
+                    .map_err(|e| format!("Invalid auth file {file_path}: {e}"))?;
+                for user in &config.users {
+                    PasswordHash::new(&user.password_hash).map_err(|e| {
+                        format!("Invalid password hash for user {}: {}", user.name, e)
+                    })?;
+                }
+                if config.users.is_empty() {
+                    return Err(format!("Auth file {file_path} has no users"));
+                }
+                Some(config)
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => None,
+            Err(e) => return Err(format!("Failed to read auth file {file_path}: {e}")),
+        };
+        Ok(Auth {
+            config,
+            sessions: RwLock::new(HashMap::new()),
+        })
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.config.is_some()
+    }
+
+    pub fn cors_origins(&self) -> &[String] {
+        self.config
+            .as_ref()
+            .map_or(&[], |config| config.cors_origins.as_slice())
+    }
+
+    pub fn session_hours(&self) -> i64 {
+        self.config
+            .as_ref()
+            .and_then(|config| config.session_hours)
+            .unwrap_or(DEFAULT_SESSION_HOURS)
+    }
+
+    /// Checks the password and starts a session. Returns its token.
+    pub fn login(&self, name: &str, password: &str) -> Option<(String, Session)> {
+        let user = self
+            .config
+            .as_ref()?
+            .users
+            .iter()
+            .find(|user| user.name == name)?;
+        let hash = PasswordHash::new(&user.password_hash).ok()?;
+        Argon2::default()
+            .verify_password(password.as_bytes(), &hash)
+            .ok()?;
+
+        let session = Session {
+            principal: Principal {
+                name: user.name.clone(),
+                role: user.role,
+            },
+            expires_at: Utc::now() + Duration::hours(self.session_hours()),
+        };
+        let token = format!("{}{}", Uuid::new_v4().simple(), Uuid::new_v4().simple());
+        let mut sessions = self.sessions.write();
+        let now = Utc::now();
+        sessions.retain(|_, session| session.expires_at > now);
+        sessions.insert(token.clone(), session.clone());
+        Some((token, session))
+    }
+
+    pub fn logout(&self, token: &str) {
+        self.sessions.write().remove(token);
+    }
+
+    pub fn session(&self, token: &str) -> Option<Session> {
+        self.sessions
+            .read()
+            .get(token)
+            .filter(|session| session.expires_at > Utc::now())
+            .cloned()
+    }
+}
+
+/// Hashes a password for `auth.toml`.
+pub fn hash_password(password: &str) -> Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_login_checks_password_and_issues_session() {
+        let auth = Auth {
+            config: Some(AuthConfig {
+                users: vec![User {
+                    name: "kai".to_string(),
+                    role: Role::Curator,
+                    password_hash: hash_password("secret").unwrap(),
+                }],
+                ..AuthConfig::default()
+            }),
+            sessions: RwLock::new(HashMap::new()),
+        };
+
+        assert!(auth.login("kai", "wrong").is_none());
+        assert!(auth.login("nobody", "secret").is_none());
+
+        let (token, session) = auth.login("kai", "secret").unwrap();
+        assert_eq!(session.principal.role, Role::Curator);
+        assert_eq!(auth.session(&token).unwrap().principal.name, "kai");
+        assert!(Role::Operator > Role::Curator);
+
+        auth.logout(&token);
+        assert!(auth.session(&token).is_none());
+    }
+}
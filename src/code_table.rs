@@ -0,0 +1,302 @@
+use crate::morse_converter::{ConversionResult, UnsupportedChar};
+use ripmors::encode_string;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use unicode_normalization::UnicodeNormalization;
+use unicode_normalization::char::is_combining_mark;
+
+const BUILTIN_TABLES: [&str; 5] = [
+    include_str!("../tables/cyrillic.json"),
+    include_str!("../tables/greek.json"),
+    include_str!("../tables/hebrew.json"),
+    include_str!("../tables/arabic.json"),
+    include_str!("../tables/korean_skats.json"),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Normalization {
+    /// Canonical decomposition, e.g. Hangul syllables into their jamo
+    Nfd,
+}
+
+/// A mapping from characters (or character sequences) to dot-dash sequences,
+/// loaded from JSON or TOML with `name`, `description`, optional
+/// `normalization` and a `codes` map. A code may contain spaces to encode a
+/// key as several letters.
+///
+/// Characters missing from the table fall back to the standard encoder, so
+/// digits and punctuation do not need to be repeated in every table.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CodeTable {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub normalization: Option<Normalization>,
+    pub codes: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CodeTableInfo {
+    pub name: String,
+    pub description: String,
+    pub builtin: bool,
+    pub entries: usize,
+}
+
+enum Token {
+    Code(String),
+    Control(char),
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0000_0100_0000_01b3)
+    })
+}
+
+impl CodeTable {
+    fn parse(content: &str, extension: &str) -> Result<Self, String> {
+        let table: CodeTable = match extension {
+            "json" => serde_json::from_str(content).map_err(|e| e.to_string())?,
+            "toml" => toml::from_str(content).map_err(|e| e.to_string())?,
+            _ => return Err(format!("unsupported table format: {extension}")),
+        };
+        table.validate()?;
+        Ok(table)
+    }
+
+    fn validate(&self) -> Result<(), String> {
+        if self.name.trim().is_empty() {
+            return Err("table name must not be empty".to_string());
+        }
+        for (key, code) in &self.codes {
+            if key.is_empty() {
+                return Err("table keys must not be empty".to_string());
+            }
+            let valid =
+                !code.trim().is_empty() && code.chars().all(|c| c == '.' || c == '-' || c == ' ');
+            if !valid {
+                return Err(format!(
+                    "invalid code {code:?} for {key:?}: only '.', '-' and ' ' are allowed"
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Hash of the table contents, so edits to a table file can be detected.
+    /// Stored with every message encoded with the table, so it is FNV-1a over
+    /// a canonical form rather than `DefaultHasher`, whose output may change
+    /// between Rust releases.
+    pub fn digest(&self) -> u64 {
+        let mut entries: Vec<(&String, &String)> = self.codes.iter().collect();
+        entries.sort();
+        let mut canonical = Vec::new();
+        if self.normalization == Some(Normalization::Nfd) {
+            canonical.extend_from_slice(b"nfd");
+        }
+        canonical.push(0);
+        for (key, code) in entries {
+            canonical.extend_from_slice(key.as_bytes());
+            canonical.push(0);
+            canonical.extend_from_slice(code.as_bytes());
+            canonical.push(0);
+        }
+        fnv1a(&canonical)
+    }
+
+    fn lookup(&self, key: &str) -> Option<&String> {
+        self.codes
+            .get(key)
+            .or_else(|| self.codes.get(&key.to_lowercase()))
+            .or_else(|| self.codes.get(&key.to_uppercase()))
+    }
+
+    pub fn encode(&self, text: &str) -> ConversionResult {
+        // Keep the original position of every (possibly decomposed) character
+        let chars: Vec<(usize, char)> = text
+            .chars()
+            .enumerate()
+            .flat_map(|(position, c)| {
+                let decomposed: Vec<char> = match self.normalization {
+                    Some(Normalization::Nfd) => c.to_string().nfd().collect(),
+                    None => vec![c],
+                };
+                decomposed.into_iter().map(move |d| (position, d))
+            })
+            .collect();
+        let max_key_len = self
+            .codes
+            .keys()
+            .map(|k| k.chars().count())
+            .max()
+            .unwrap_or(1);
+
+        let mut tokens = Vec::new();
+        let mut unsupported = Vec::new();
+        let mut i = 0;
+        'outer: while i < chars.len() {
+            for len in (1..=max_key_len.min(chars.len() - i)).rev() {
+                let key: String = chars[i..i + len].iter().map(|(_, c)| c).collect();
+                if let Some(code) = self.lookup(&key) {
+                    tokens.push(Token::Code(code.clone()));
+                    i += len;
+                    continue 'outer;
+                }
+            }
+
+            let (position, c) = chars[i];
+            i += 1;
+            match c {
+                '\n' | '\r' | '\t' => tokens.push(Token::Control(c)),
+                c if c.is_whitespace() => tokens.push(Token::Code("/".to_string())),
+                c if self.normalization.is_some() && is_combining_mark(c) => {}
+                c => {
+                    let code = encode_string(&c.to_string());
+                    if code.is_empty() {
+                        unsupported.push(UnsupportedChar {
+                            character: c,
+                            position,
+                        });
+                    } else {
+                        tokens.push(Token::Code(code));
+                    }
+                }
+            }
+        }
+
+        // Same layout as ripmors: letters separated by spaces, control characters kept as-is
+        let mut morse_code = String::new();
+        for token in tokens {
+            match token {
+                Token::Code(code) => {
+                    morse_code.push_str(&code);
+                    morse_code.push(' ');
+                }
+                Token::Control(c) => {
+                    if morse_code.ends_with(' ') {
+                        morse_code.pop();
+                    }
+                    morse_code.push(c);
+                }
+            }
+        }
+        if morse_code.ends_with(' ') {
+            morse_code.pop();
+        }
+
+        ConversionResult {
+            morse_code,
+            unsupported,
+            encoded_with: String::new(),
+        }
+    }
+}
+
+pub struct CodeTableRegistry {
+    tables: HashMap<String, CodeTable>,
+    builtin: Vec<String>,
+}
+
+impl Default for CodeTableRegistry {
+    fn default() -> Self {
+        let mut tables = HashMap::new();
+        for content in BUILTIN_TABLES {
+            let table = CodeTable::parse(content, "json").expect("built-in code table is valid");
+            tables.insert(table.name.clone(), table);
+        }
+        let builtin = tables.keys().cloned().collect();
+        CodeTableRegistry { tables, builtin }
+    }
+}
+
+impl CodeTableRegistry {
+    /// Built-in tables plus every `.json`/`.toml` table found in `dir`.
+    /// User tables with the same name replace the built-in one.
+    pub fn load(dir: &str) -> Self {
+        let mut registry = Self::default();
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => {
+                println!(
+                    "Code table directory {} not found, using built-in tables",
+                    dir
+                );
+                return registry;
+            }
+        };
+
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let extension = path
+                .extension()
+                .and_then(|e| e.to_str())
+                .unwrap_or_default()
+                .to_lowercase();
+            if extension != "json" && extension != "toml" {
+                continue;
+            }
+            match fs::read_to_string(&path)
+                .map_err(|e| e.to_string())
+                .and_then(|content| CodeTable::parse(&content, &extension))
+            {
+                Ok(table) => {
+                    println!(
+                        "Loaded code table '{}' from {} ({} entries)",
+                        table.name,
+                        path.display(),
+                        table.codes.len()
+                    );
+                    registry.builtin.retain(|name| name != &table.name);
+                    registry.tables.insert(table.name.clone(), table);
+                }
+                Err(e) => eprintln!("Failed to load code table {}: {}", path.display(), e),
+            }
+        }
+        registry
+    }
+
+    pub fn get(&self, name: &str) -> Option<&CodeTable> {
+        self.tables.get(name)
+    }
+
+    pub fn list(&self) -> Vec<CodeTableInfo> {
+        let mut infos: Vec<CodeTableInfo> = self
+            .tables
+            .values()
+            .map(|table| CodeTableInfo {
+                name: table.name.clone(),
+                description: table.description.clone(),
+                builtin: self.builtin.contains(&table.name),
+                entries: table.codes.len(),
+            })
+            .collect();
+        infos.sort_by(|a, b| a.name.cmp(&b.name));
+        infos
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_digest_is_fixed_and_follows_contents() {
+        let mut table = CodeTable {
+            name: "test".to_string(),
+            description: String::new(),
+            normalization: None,
+            codes: [("b", "-..."), ("a", ".-")]
+                .map(|(key, code)| (key.to_string(), code.to_string()))
+                .into(),
+        };
+        // Must not change between builds: stored fingerprints depend on it
+        assert_eq!(table.digest(), 0xf0ea_e489_9bbc_968c);
+
+        table.codes.insert("c".to_string(), "-.-.".to_string());
+        assert_ne!(table.digest(), 0xf0ea_e489_9bbc_968c);
+    }
+}
@@ -0,0 +1,163 @@
+use crate::message_transformer::TransformerConfig;
+use crate::storage;
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io;
+
+/// When a config change takes effect.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ApplyMode {
+    /// Interrupt the message being played
+    #[default]
+    Immediate,
+    /// Let the current message finish first
+    NextMessage,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FieldChange {
+    pub field: String,
+    pub old: Value,
+    pub new: Value,
+}
+
+/// One config version. `config` is the full config after the change, so any
+/// version can be restored.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConfigAuditEntry {
+    pub version: u64,
+    pub at: DateTime<Utc>,
+    pub actor: String,
+    /// What made the change, e.g. `put`, `patch`, `preset:evening`, `revert:3`
+    pub source: String,
+    pub apply: ApplyMode,
+    pub changes: Vec<FieldChange>,
+    pub config: TransformerConfig,
+}
+
+/// Append-only log of config changes, one JSON entry per line, with
+/// increasing version numbers.
+pub struct ConfigAuditLog {
+    file_path: String,
+    last_version: Mutex<u64>,
+}
+
+/// Top-level fields whose values differ between `old` and `new`
+pub fn diff(old: &TransformerConfig, new: &TransformerConfig) -> Vec<FieldChange> {
+    let (Ok(Value::Object(old)), Ok(Value::Object(new))) =
+        (serde_json::to_value(old), serde_json::to_value(new))
+    else {
+        return Vec::new();
+    };
+    new.into_iter()
+        .filter_map(|(field, new_value)| {
+            let old_value = old.get(&field).cloned().unwrap_or(Value::Null);
+            (old_value != new_value).then_some(FieldChange {
+                field,
+                old: old_value,
+                new: new_value,
+            })
+        })
+        .collect()
+}
+
+impl ConfigAuditLog {
+    pub fn open(file_path: &str) -> Self {
+        let last_version = match storage::read_json_lines::<ConfigAuditEntry>(file_path) {
+            Ok(entries) => entries.iter().map(|e| e.version).max().unwrap_or(0),
+            Err(e) => {
+                eprintln!("Failed to read config audit log {}: {}", file_path, e);
+                0
+            }
+        };
+        ConfigAuditLog {
+            file_path: file_path.to_string(),
+            last_version: Mutex::new(last_version),
+        }
+    }
+
+    /// Appends a new version if `new` differs from `old`. Returns the entry,
+    /// or `None` when nothing changed.
+    pub fn record(
+        &self,
+        actor: &str,
+        source: &str,
+        apply: ApplyMode,
+        old: &TransformerConfig,
+        new: &TransformerConfig,
+    ) -> io::Result<Option<ConfigAuditEntry>> {
+        let changes = diff(old, new);
+        if changes.is_empty() {
+            return Ok(None);
+        }
+        self.append(actor, source, apply, changes, new).map(Some)
+    }
+
+    /// Logs the config found at startup when it differs from the newest
+    /// version, e.g. on first run or after the file was edited by hand, so it
+    /// can be reverted to.
+    pub fn record_startup(&self, config: &TransformerConfig) -> io::Result<()> {
+        let changes = match self.list()?.into_iter().next() {
+            Some(latest) if latest.config == *config => return Ok(()),
+            Some(latest) => diff(&latest.config, config),
+            None => Vec::new(),
+        };
+        self.append("system", "startup", ApplyMode::Immediate, changes, config)?;
+        Ok(())
+    }
+
+    fn append(
+        &self,
+        actor: &str,
+        source: &str,
+        apply: ApplyMode,
+        changes: Vec<FieldChange>,
+        config: &TransformerConfig,
+    ) -> io::Result<ConfigAuditEntry> {
+        let mut last_version = self.last_version.lock();
+        let entry = ConfigAuditEntry {
+            version: *last_version + 1,
+            at: Utc::now(),
+            actor: actor.to_string(),
+            source: source.to_string(),
+            apply,
+            changes,
+            config: config.clone(),
+        };
+        storage::append_json_line(&self.file_path, &entry)?;
+        *last_version = entry.version;
+        Ok(entry)
+    }
+
+    /// All versions, newest first
+    pub fn list(&self) -> io::Result<Vec<ConfigAuditEntry>> {
+        let mut entries: Vec<ConfigAuditEntry> = storage::read_json_lines(&self.file_path)?;
+        entries.sort_by_key(|e| std::cmp::Reverse(e.version));
+        Ok(entries)
+    }
+
+    pub fn get(&self, version: u64) -> io::Result<Option<ConfigAuditEntry>> {
+        Ok(self.list()?.into_iter().find(|e| e.version == version))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_lists_changed_fields_only() {
+        let old = TransformerConfig::default();
+        let mut new = old.clone();
+        assert!(diff(&old, &new).is_empty());
+
+        new.tempo_choices = vec![120, 240];
+        let changes = diff(&old, &new);
+        assert_eq!(changes.len(), 1);
+        assert_eq!(changes[0].field, "tempo_choices");
+        assert_eq!(changes[0].new, serde_json::json!([120, 240]));
+    }
+}
@@ -0,0 +1,213 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use unicode_normalization::UnicodeNormalization;
+
+/// Layout of `content_filter.toml`
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct FilterConfig {
+    /// Words and phrases, Japanese or English. Matched after normalization,
+    /// so one entry catches katakana, hiragana, half-width and spaced-out
+    /// variants.
+    pub blocklist: Vec<String>,
+    /// Longest submission in characters
+    pub max_chars: usize,
+    /// Longest playback in seconds, at the slowest configured tempo
+    pub max_duration_secs: u64,
+    /// Submissions that pass every check are played without review
+    pub auto_approve: bool,
+}
+
+impl Default for FilterConfig {
+    fn default() -> Self {
+        FilterConfig {
+            blocklist: Vec::new(),
+            max_chars: 280,
+            max_duration_secs: 600,
+            auto_approve: false,
+        }
+    }
+}
+
+/// Why the filter held a submission back for moderation
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum ContentFlag {
+    BlockedTerm { term: String },
+    TooLong { chars: usize, max_chars: usize },
+    TooLongToPlay { duration_ms: u64, max_ms: u64 },
+}
+
+impl std::fmt::Display for ContentFlag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ContentFlag::BlockedTerm { term } => write!(f, "contains blocked term \"{term}\""),
+            ContentFlag::TooLong { chars, max_chars } => {
+                write!(f, "{chars} characters, the limit is {max_chars}")
+            }
+            ContentFlag::TooLongToPlay {
+                duration_ms,
+                max_ms,
+            } => write!(
+                f,
+                "plays for {} s, the limit is {} s",
+                duration_ms.div_ceil(1000),
+                max_ms / 1000
+            ),
+        }
+    }
+}
+
+/// Checks visitor submissions before they can be played.
+pub struct ContentFilter {
+    config: FilterConfig,
+    /// Blocklist entries in normalized form, paired with the original
+    blocked: Vec<(String, String)>,
+}
+
+impl ContentFilter {
+    pub fn new(config: FilterConfig) -> Self {
+        let blocked = config
+            .blocklist
+            .iter()
+            .map(|term| (normalize(term), term.clone()))
+            .filter(|(normalized, _)| !normalized.is_empty())
+            .collect();
+        ContentFilter { config, blocked }
+    }
+
+    /// Reads `file_path`; without the file only the default limits apply.
+    pub fn load(file_path: &str) -> Result<Self, String> {
+        let config = match fs::read_to_string(file_path) {
+            Ok(content) => toml::from_str(&content)
+                .map_err(|e| format!("Invalid content filter file {file_path}: {e}"))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => FilterConfig::default(),
+            Err(e) => return Err(format!("Failed to read {file_path}: {e}")),
+        };
+        Ok(Self::new(config))
+    }
+
+    pub fn auto_approve(&self) -> bool {
+        self.config.auto_approve
+    }
+
+    /// Returns every reason to hold `text` back; empty means it passed.
+    /// Blocked terms match anywhere in the text, so a flag may be a false
+    /// positive for a moderator to approve.
+    pub fn check(&self, text: &str, duration_ms: u64) -> Vec<ContentFlag> {
+        let mut flags = Vec::new();
+
+        let normalized = normalize(text);
+        for (term, original) in &self.blocked {
+            if normalized.contains(term.as_str()) {
+                flags.push(ContentFlag::BlockedTerm {
+                    term: original.clone(),
+                });
+            }
+        }
+
+        let chars = text.chars().count();
+        if chars > self.config.max_chars {
+            flags.push(ContentFlag::TooLong {
+                chars,
+                max_chars: self.config.max_chars,
+            });
+        }
+
+        let max_ms = self.config.max_duration_secs.saturating_mul(1000);
+        if duration_ms > max_ms {
+            flags.push(ContentFlag::TooLongToPlay {
+                duration_ms,
+                max_ms,
+            });
+        }
+        flags
+    }
+}
+
+/// Folds the ways a word can be written into one form for matching:
+/// compatibility normalization (half-width kana, full-width letters),
+/// lowercase, katakana to hiragana, small kana to full size, and without
+/// spaces, punctuation or the long vowel mark.
+pub fn normalize(text: &str) -> String {
+    text.nfkc()
+        .flat_map(char::to_lowercase)
+        .filter(|c| c.is_alphanumeric() && *c != 'ー')
+        .map(|c| full_size_kana(katakana_to_hiragana(c)))
+        .collect()
+}
+
+fn katakana_to_hiragana(c: char) -> char {
+    match c {
+        'ァ'..='ヶ' => char::from_u32(c as u32 - 0x60).unwrap_or(c),
+        _ => c,
+    }
+}
+
+fn full_size_kana(c: char) -> char {
+    match c {
+        'ぁ' => 'あ',
+        'ぃ' => 'い',
+        'ぅ' => 'う',
+        'ぇ' => 'え',
+        'ぉ' => 'お',
+        'っ' => 'つ',
+        'ゃ' => 'や',
+        'ゅ' => 'ゆ',
+        'ょ' => 'よ',
+        'ゎ' => 'わ',
+        'ゕ' => 'か',
+        'ゖ' => 'け',
+        _ => c,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalize_folds_kana_and_width_variants() {
+        let expected = normalize("ばか");
+        for variant in ["バカ", "ﾊﾞｶ", "ば か", "バ・カ"] {
+            assert_eq!(normalize(variant), expected, "{variant}");
+        }
+        assert_eq!(normalize("Ｓpam!"), "spam");
+        assert_eq!(normalize("キャッツ"), normalize("きやつつ"));
+    }
+
+    #[test]
+    fn test_check_reports_each_reason() {
+        let filter = ContentFilter::new(FilterConfig {
+            blocklist: vec!["バカ".to_string(), "spam".to_string()],
+            max_chars: 10,
+            max_duration_secs: 5,
+            auto_approve: false,
+        });
+
+        assert!(filter.check("こんにちは", 1000).is_empty());
+        assert_eq!(
+            filter.check("ﾊﾞｶ", 0),
+            vec![ContentFlag::BlockedTerm {
+                term: "バカ".to_string()
+            }]
+        );
+        assert_eq!(
+            filter.check("S P A M everywhere", 6000),
+            vec![
+                ContentFlag::BlockedTerm {
+                    term: "spam".to_string()
+                },
+                ContentFlag::TooLong {
+                    chars: 18,
+                    max_chars: 10
+                },
+                ContentFlag::TooLongToPlay {
+                    duration_ms: 6000,
+                    max_ms: 5000
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,118 @@
+/// Leading characters that make a spreadsheet read a cell as a formula
+const FORMULA_STARTS: [char; 4] = ['=', '+', '-', '@'];
+
+/// Whether a cell needs the `'` guard: it starts with a formula character,
+/// possibly behind guards of its own, which are then kept on a round trip.
+fn needs_formula_guard(value: &str) -> bool {
+    value.trim_start_matches('\'').starts_with(FORMULA_STARTS)
+}
+
+/// Quotes a CSV field when it contains a separator, quote or line break, and
+/// prefixes `'` when a spreadsheet would otherwise run it as a formula.
+pub fn escape(value: &str) -> String {
+    let value = if needs_formula_guard(value) {
+        format!("'{value}")
+    } else {
+        value.to_string()
+    };
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+/// Splits RFC 4180 CSV into rows of fields. Quoted fields may contain commas,
+/// doubled quotes and line breaks; blank lines are skipped. The formula guard
+/// `escape` adds is removed again.
+pub fn parse(input: &str) -> Result<Vec<Vec<String>>, String> {
+    let input = input.strip_prefix('\u{feff}').unwrap_or(input);
+    let mut rows = Vec::new();
+    let mut row = Vec::new();
+    let mut field = String::new();
+    let mut in_quotes = false;
+    let mut line = 1;
+    let mut quote_line = 0;
+
+    let mut chars = input.chars().peekable();
+    while let Some(c) = chars.next() {
+        if in_quotes {
+            match c {
+                '"' if chars.peek() == Some(&'"') => {
+                    chars.next();
+                    field.push('"');
+                }
+                '"' => in_quotes = false,
+                c => {
+                    if c == '\n' {
+                        line += 1;
+                    }
+                    field.push(c);
+                }
+            }
+            continue;
+        }
+        match c {
+            '"' if field.is_empty() => {
+                in_quotes = true;
+                quote_line = line;
+            }
+            ',' => row.push(std::mem::take(&mut field)),
+            '\r' if chars.peek() == Some(&'\n') => {}
+            '\n' | '\r' => {
+                row.push(std::mem::take(&mut field));
+                rows.push(std::mem::take(&mut row));
+                line += 1;
+            }
+            c => field.push(c),
+        }
+    }
+    if in_quotes {
+        return Err(format!(
+            "unterminated quoted field starting on line {quote_line}"
+        ));
+    }
+    if !field.is_empty() || !row.is_empty() {
+        row.push(field);
+        rows.push(row);
+    }
+
+    rows.retain(|row| !(row.len() == 1 && row[0].trim().is_empty()));
+    for field in rows.iter_mut().flatten() {
+        if field.starts_with('\'') && needs_formula_guard(field) {
+            field.remove(0);
+        }
+    }
+    Ok(rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_round_trips_escaped_fields() {
+        let fields = ["plain", "a, b", "say \"hi\"", "two\nlines", "=1+1", "'-.-"];
+        let line = fields.map(escape).join(",");
+        let input = format!("text,other\r\n{line}\r\n\r\nlast,\n");
+
+        let rows = parse(&input).unwrap();
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[0], vec!["text", "other"]);
+        assert_eq!(rows[1], fields);
+        assert_eq!(rows[2], vec!["last", ""]);
+
+        assert!(parse("\"open").is_err());
+    }
+
+    #[test]
+    fn test_escape_guards_formula_cells() {
+        assert_eq!(escape("=HYPERLINK(\"x\")"), "\"'=HYPERLINK(\"\"x\"\")\"");
+        assert_eq!(escape("+1"), "'+1");
+        assert_eq!(escape("-.-"), "'-.-");
+        assert_eq!(escape("@SUM(A1)"), "'@SUM(A1)");
+        assert_eq!(escape("'=1"), "''=1");
+        assert_eq!(escape("a=b"), "a=b");
+        assert_eq!(escape("'quoted"), "'quoted");
+    }
+}
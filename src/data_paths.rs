@@ -0,0 +1,133 @@
+use std::env;
+use std::path::{Path, PathBuf};
+
+const DATA_DIR_ENV: &str = "MORSE_DATA_DIR";
+const STATIC_DIR_ENV: &str = "MORSE_STATIC_DIR";
+const STORAGE_ENV: &str = "MORSE_STORAGE";
+
+const USAGE: &str = "Usage: morse-code-converter [--data-dir <path>] [--static-dir <path>] [--storage <json|sqlite>]
+       morse-code-converter hash-password < password.txt
+
+Options:
+  --data-dir <path>    Directory holding messages, config, presets, audit
+                       and play logs, code tables, auth.toml,
+                       content_filter.toml and limits.toml
+                       (env: MORSE_DATA_DIR, default: current directory)
+  --static-dir <path>  Directory served under /static; its index.html is
+                       also the page at /
+                       (env: MORSE_STATIC_DIR, default: <data-dir>/static)
+  --storage <backend>  Message storage: json (messages.json) or sqlite
+                       (messages.db, requires the sqlite feature); play
+                       history is always play_history.jsonl
+                       (env: MORSE_STORAGE, default: json)
+  -h, --help           Print this help
+
+hash-password prints the hash of a password read from stdin, for the
+password_hash of a user in auth.toml.";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StorageBackend {
+    Json,
+    Sqlite,
+}
+
+impl std::str::FromStr for StorageBackend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(StorageBackend::Json),
+            "sqlite" => Ok(StorageBackend::Sqlite),
+            _ => Err(format!("Unknown storage backend: {s}")),
+        }
+    }
+}
+
+/// Locations of every file the service reads or writes, resolved once at
+/// startup against the data directory, and the message storage backend.
+#[derive(Debug, Clone)]
+pub struct DataPaths {
+    pub data_dir: PathBuf,
+    pub storage: StorageBackend,
+    pub messages_file: String,
+    pub database_file: String,
+    pub config_file: String,
+    pub presets_file: String,
+    pub audit_file: String,
+    pub auth_file: String,
+    pub content_filter_file: String,
+    pub limits_file: String,
+    pub history_file: String,
+    pub code_tables_dir: String,
+    pub static_dir: String,
+}
+
+impl DataPaths {
+    pub fn new(data_dir: &Path, static_dir: Option<&Path>, storage: StorageBackend) -> Self {
+        let resolve = |name: &str| data_dir.join(name).to_string_lossy().into_owned();
+        DataPaths {
+            data_dir: data_dir.to_path_buf(),
+            storage,
+            messages_file: resolve("messages.json"),
+            database_file: resolve("messages.db"),
+            config_file: resolve("transformer_config.json"),
+            presets_file: resolve("config_presets.json"),
+            audit_file: resolve("config_audit.jsonl"),
+            auth_file: resolve("auth.toml"),
+            content_filter_file: resolve("content_filter.toml"),
+            limits_file: resolve("limits.toml"),
+            history_file: resolve("play_history.jsonl"),
+            code_tables_dir: resolve("code_tables"),
+            static_dir: match static_dir {
+                Some(dir) => dir.to_string_lossy().into_owned(),
+                None => resolve("static"),
+            },
+        }
+    }
+
+    /// Reads the options from the command line, falling back to the
+    /// environment. Exits with usage on unknown arguments.
+    pub fn from_args_and_env() -> Self {
+        let mut data_dir = env::var_os(DATA_DIR_ENV).map(PathBuf::from);
+        let mut static_dir = env::var_os(STATIC_DIR_ENV).map(PathBuf::from);
+        let mut storage = env::var(STORAGE_ENV).ok();
+
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let (flag, inline_value) = match arg.split_once('=') {
+                Some((flag, value)) => (flag.to_string(), Some(value.to_string())),
+                None => (arg.clone(), None),
+            };
+            match flag.as_str() {
+                "--data-dir" | "--static-dir" | "--storage" => {}
+                "-h" | "--help" => {
+                    println!("{USAGE}");
+                    std::process::exit(0);
+                }
+                _ => {
+                    eprintln!("Unknown argument: {arg}\n\n{USAGE}");
+                    std::process::exit(2);
+                }
+            }
+            let Some(value) = inline_value.or_else(|| args.next()) else {
+                eprintln!("Missing value for {flag}\n\n{USAGE}");
+                std::process::exit(2);
+            };
+            match flag.as_str() {
+                "--data-dir" => data_dir = Some(PathBuf::from(value)),
+                "--static-dir" => static_dir = Some(PathBuf::from(value)),
+                _ => storage = Some(value),
+            }
+        }
+
+        let storage = match storage.as_deref().unwrap_or("json").parse() {
+            Ok(storage) => storage,
+            Err(e) => {
+                eprintln!("{e}\n\n{USAGE}");
+                std::process::exit(2);
+            }
+        };
+        let data_dir = data_dir.unwrap_or_else(|| PathBuf::from("."));
+        Self::new(&data_dir, static_dir.as_deref(), storage)
+    }
+}
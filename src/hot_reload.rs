@@ -0,0 +1,206 @@
+use crate::storage;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeSet;
+use std::fs;
+
+/// Turns file contents into the JSON shape they are merged in, or `None` when
+/// the file does not parse.
+pub type Normalize = fn(&str) -> Option<Value>;
+
+/// Result of picking up an edit made outside the service, reported through
+/// `/api/status`.
+#[derive(Debug, Clone, Serialize)]
+pub struct ReloadEvent {
+    pub file: String,
+    pub at: DateTime<Utc>,
+    pub applied: bool,
+    /// Values edited both on disk and in memory; the disk version was kept
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub conflicts: Vec<String>,
+    /// Why the edit was rejected
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub errors: Vec<String>,
+}
+
+impl ReloadEvent {
+    pub fn applied(file: &str, conflicts: Vec<String>) -> Self {
+        ReloadEvent {
+            file: file.to_string(),
+            at: Utc::now(),
+            applied: true,
+            conflicts,
+            errors: Vec::new(),
+        }
+    }
+
+    pub fn rejected(file: &str, errors: Vec<String>) -> Self {
+        ReloadEvent {
+            file: file.to_string(),
+            at: Utc::now(),
+            applied: false,
+            conflicts: Vec::new(),
+            errors,
+        }
+    }
+}
+
+/// A persisted file polled for changes made by other programs. Keeps the
+/// contents this process last synced with as the base for three-way merges.
+pub struct WatchedFile {
+    pub path: String,
+    normalize: Normalize,
+    last_seen: Option<u64>,
+    base: Value,
+}
+
+impl WatchedFile {
+    /// Starts tracking `path` from its current contents.
+    pub fn new(path: &str, normalize: Normalize) -> Self {
+        let mut watched = WatchedFile {
+            path: path.to_string(),
+            normalize,
+            last_seen: None,
+            base: Value::Null,
+        };
+        match fs::read_to_string(path) {
+            Ok(content) => watched.accept(&content),
+            // Nothing written yet: the first save starts the tracking
+            Err(_) => storage::mark_synced(path, b""),
+        }
+        watched
+    }
+
+    /// Returns the new contents when the file was changed by another program
+    /// since the last call. Writes by this process only move the merge base.
+    pub fn poll(&mut self) -> Option<String> {
+        let content = fs::read_to_string(&self.path).ok()?;
+        let hash = storage::content_hash(content.as_bytes());
+        if self.last_seen == Some(hash) {
+            return None;
+        }
+        self.last_seen = Some(hash);
+
+        if storage::synced_hash(&self.path) == Some(hash) {
+            if let Some(base) = (self.normalize)(&content) {
+                self.base = base;
+            }
+            return None;
+        }
+        Some(content)
+    }
+
+    /// Marks `content` as handled, whether it was applied or rejected, so
+    /// saving resumes. A rejected edit ends up in the snapshot rotation on the
+    /// next save.
+    pub fn accept(&mut self, content: &str) {
+        storage::mark_synced(&self.path, content.as_bytes());
+        self.last_seen = Some(storage::content_hash(content.as_bytes()));
+        if let Some(base) = (self.normalize)(content) {
+            self.base = base;
+        }
+    }
+
+    pub fn base(&self) -> &Value {
+        &self.base
+    }
+}
+
+/// Three-way merge of the disk and in-memory versions against their common
+/// base. Objects merge key by key; a value changed on both sides is a
+/// conflict, resolved in favour of the disk, and its path is added to
+/// `conflicts`.
+pub fn merge3(base: &Value, disk: &Value, memory: &Value, conflicts: &mut Vec<String>) -> Value {
+    merge_value(Some(base), Some(disk), Some(memory), "", conflicts).unwrap_or(Value::Null)
+}
+
+fn merge_value(
+    base: Option<&Value>,
+    disk: Option<&Value>,
+    memory: Option<&Value>,
+    path: &str,
+    conflicts: &mut Vec<String>,
+) -> Option<Value> {
+    if disk == memory || memory == base {
+        return disk.cloned();
+    }
+    if disk == base {
+        return memory.cloned();
+    }
+    if let (Some(Value::Object(disk)), Some(Value::Object(memory))) = (disk, memory) {
+        let base = base.and_then(Value::as_object);
+        let keys: BTreeSet<&String> = disk.keys().chain(memory.keys()).collect();
+        let mut merged = Map::new();
+        for key in keys {
+            let child = if path.is_empty() {
+                key.clone()
+            } else {
+                format!("{path}.{key}")
+            };
+            let value = merge_value(
+                base.and_then(|base| base.get(key)),
+                disk.get(key),
+                memory.get(key),
+                &child,
+                conflicts,
+            );
+            if let Some(value) = value {
+                merged.insert(key.clone(), value);
+            }
+        }
+        return Some(Value::Object(merged));
+    }
+    conflicts.push(path.to_string());
+    disk.cloned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_merge_keeps_changes_from_both_sides() {
+        let base = json!({
+            "a": { "text": "hello", "send_count": 1 },
+            "b": { "text": "bye", "send_count": 0 },
+        });
+        // Edited by hand: new text for a, b removed, c added
+        let disk = json!({
+            "a": { "text": "hello world", "send_count": 1 },
+            "c": { "text": "new", "send_count": 0 },
+        });
+        // Meanwhile a was played
+        let memory = json!({
+            "a": { "text": "hello", "send_count": 2 },
+            "b": { "text": "bye", "send_count": 0 },
+        });
+
+        let mut conflicts = Vec::new();
+        let merged = merge3(&base, &disk, &memory, &mut conflicts);
+        assert!(conflicts.is_empty());
+        assert_eq!(
+            merged,
+            json!({
+                "a": { "text": "hello world", "send_count": 2 },
+                "c": { "text": "new", "send_count": 0 },
+            })
+        );
+    }
+
+    #[test]
+    fn test_merge_conflict_prefers_disk() {
+        let base = json!({ "tempo_choices": [400], "lamp_tempo_ms": 400 });
+        let disk = json!({ "tempo_choices": [500], "lamp_tempo_ms": 400 });
+        let memory = json!({ "tempo_choices": [600], "lamp_tempo_ms": 300 });
+
+        let mut conflicts = Vec::new();
+        let merged = merge3(&base, &disk, &memory, &mut conflicts);
+        assert_eq!(conflicts, vec!["tempo_choices".to_string()]);
+        assert_eq!(
+            merged,
+            json!({ "tempo_choices": [500], "lamp_tempo_ms": 300 })
+        );
+    }
+}
@@ -0,0 +1,165 @@
+use crate::message_transformer::FieldError;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Deserializer, Serialize};
+
+/// When a message may play. Stored flattened into the message; messages
+/// from before these fields existed are enabled without limits.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Lifecycle {
+    pub enabled: bool,
+    pub active_from: Option<DateTime<Utc>>,
+    pub active_until: Option<DateTime<Utc>>,
+    /// Plays after which the message retires
+    pub max_sends: Option<u32>,
+}
+
+impl Default for Lifecycle {
+    fn default() -> Self {
+        Lifecycle {
+            enabled: true,
+            active_from: None,
+            active_until: None,
+            max_sends: None,
+        }
+    }
+}
+
+/// Why a message does or does not play right now
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LifecycleState {
+    Active,
+    Disabled,
+    /// `active_from` is still ahead
+    Scheduled,
+    /// `active_until` has passed
+    Expired,
+    /// `max_sends` plays reached
+    Retired,
+}
+
+/// Lifecycle fields of a create or update request. Omitted fields keep
+/// their value; `null` clears a limit.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct LifecycleChanges {
+    pub enabled: Option<bool>,
+    #[serde(deserialize_with = "present")]
+    pub active_from: Option<Option<DateTime<Utc>>>,
+    #[serde(deserialize_with = "present")]
+    pub active_until: Option<Option<DateTime<Utc>>>,
+    #[serde(deserialize_with = "present")]
+    pub max_sends: Option<Option<u32>>,
+}
+
+/// Tells a field sent as `null` (`Some(None)`) from one left out (`None`).
+fn present<'de, D, T>(deserializer: D) -> Result<Option<Option<T>>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    Option::deserialize(deserializer).map(Some)
+}
+
+impl Lifecycle {
+    /// Applies `changes` if the result is consistent; otherwise leaves the
+    /// lifecycle untouched and returns every problem.
+    pub fn apply(&mut self, changes: LifecycleChanges) -> Result<(), Vec<FieldError>> {
+        let mut updated = self.clone();
+        if let Some(enabled) = changes.enabled {
+            updated.enabled = enabled;
+        }
+        if let Some(active_from) = changes.active_from {
+            updated.active_from = active_from;
+        }
+        if let Some(active_until) = changes.active_until {
+            updated.active_until = active_until;
+        }
+        if let Some(max_sends) = changes.max_sends {
+            updated.max_sends = max_sends;
+        }
+
+        let mut errors = Vec::new();
+        if let (Some(from), Some(until)) = (updated.active_from, updated.active_until)
+            && until <= from
+        {
+            errors.push(FieldError::new(
+                "active_until",
+                "must be later than active_from",
+            ));
+        }
+        if updated.max_sends == Some(0) {
+            errors.push(FieldError::new("max_sends", "must be at least 1"));
+        }
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        *self = updated;
+        Ok(())
+    }
+
+    pub fn state(&self, now: DateTime<Utc>, send_count: u32) -> LifecycleState {
+        if !self.enabled {
+            LifecycleState::Disabled
+        } else if self.max_sends.is_some_and(|max| send_count >= max) {
+            LifecycleState::Retired
+        } else if self.active_until.is_some_and(|until| now >= until) {
+            LifecycleState::Expired
+        } else if self.active_from.is_some_and(|from| now < from) {
+            LifecycleState::Scheduled
+        } else {
+            LifecycleState::Active
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::Duration;
+
+    #[test]
+    fn test_state_follows_flag_window_and_plays() {
+        let now = Utc::now();
+        let mut lifecycle = Lifecycle::default();
+        assert_eq!(lifecycle.state(now, 100), LifecycleState::Active);
+
+        let changes: LifecycleChanges = serde_json::from_value(serde_json::json!({
+            "active_from": now + Duration::hours(1),
+            "max_sends": 3,
+        }))
+        .unwrap();
+        lifecycle.apply(changes).unwrap();
+        assert_eq!(lifecycle.state(now, 0), LifecycleState::Scheduled);
+        assert_eq!(
+            lifecycle.state(now + Duration::hours(2), 2),
+            LifecycleState::Active
+        );
+        assert_eq!(
+            lifecycle.state(now + Duration::hours(2), 3),
+            LifecycleState::Retired
+        );
+
+        // null clears, omitted keeps
+        let changes: LifecycleChanges =
+            serde_json::from_value(serde_json::json!({ "active_from": null, "enabled": false }))
+                .unwrap();
+        lifecycle.apply(changes).unwrap();
+        assert_eq!(lifecycle.active_from, None);
+        assert_eq!(lifecycle.max_sends, Some(3));
+        assert_eq!(lifecycle.state(now, 0), LifecycleState::Disabled);
+
+        let before = lifecycle.clone();
+        let errors = lifecycle
+            .apply(LifecycleChanges {
+                active_from: Some(Some(now)),
+                active_until: Some(Some(now - Duration::hours(1))),
+                max_sends: Some(Some(0)),
+                ..LifecycleChanges::default()
+            })
+            .unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(lifecycle, before);
+    }
+}
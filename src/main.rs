@@ -1,36 +1,80 @@
+// The route tree wrapped in CORS is deeper than the default limit allows
+#![recursion_limit = "256"]
+
+mod api_error;
+mod auth;
+mod code_table;
+mod config_audit;
+mod content_filter;
+mod csv;
+mod data_paths;
+mod hot_reload;
+mod lifecycle;
+mod message_io;
+mod message_meta;
+mod message_query;
 mod message_transformer;
+mod migration;
 mod morse_converter;
+mod play_log;
+mod presets;
+mod rate_limit;
+mod repository;
 mod serial_send;
+mod storage;
 
-use chrono::{DateTime, Utc};
+use api_error::ApiError;
+use auth::{Auth, Principal, Role, SESSION_COOKIE};
+use chrono::{DateTime, Local, NaiveDate, Utc};
 use clokwerk::{Scheduler, TimeUnits};
+use code_table::CodeTableRegistry;
+use config_audit::{ApplyMode, ConfigAuditLog};
+use content_filter::{ContentFilter, ContentFlag};
+use data_paths::{DataPaths, StorageBackend};
+use hot_reload::{ReloadEvent, WatchedFile};
+use lifecycle::{Lifecycle, LifecycleChanges, LifecycleState};
+use message_io::{ImportItemResult, ImportReport, ImportStatus, TransferFormat};
+use message_meta::{MessageMeta, MetaChanges, normalize_tag};
+use message_query::{Cursor, MAX_PAGE_SIZE, Search, SortKey, SortOrder};
 use message_transformer::{
-    TransformerConfig, convert_dash_message, convert_dot_message, convert_space_message,
+    FieldError, MorseElement, TransformerConfig, convert_dash_message, convert_dot_message,
+    convert_space_message, element_frame, element_timing, playback_duration_ms, playback_timeline,
+    slowest_tempo_ms,
 };
-use morse_converter::MorseConverter;
+use migration::CONFIG_SCHEMA;
+use morse_converter::{MorseConverter, NewlinePolicy, UnsupportedChar, frame_with_prosigns};
 use parking_lot::RwLock;
+use play_log::{InterruptReason, PlayLog, PlayMode, PlayRecord};
+use presets::{PresetBook, PresetError};
 use rand::prelude::*;
 use rand::rng;
+use rate_limit::{LimitKind, Limits};
+#[cfg(feature = "sqlite")]
+use repository::SqliteMessageRepository;
+use repository::{JsonMessageRepository, MessageRepository, log_write_error, parse_messages};
 use serde::{Deserialize, Serialize};
 use serial_send::SerialSender;
 use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
+use std::net::SocketAddr;
 use std::sync::Arc;
 use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::thread;
 use std::time::{Duration, Instant};
+use storage::LoadStatus;
 use uuid::Uuid;
 use warp::Filter;
 
 static CONSECUTIVE_INSTRUMENT_COUNT: AtomicU32 = AtomicU32::new(0);
 static CONFIG_CHANGED: AtomicBool = AtomicBool::new(false);
+static SKIP_REQUESTED: AtomicBool = AtomicBool::new(false);
 
 use lazy_static::lazy_static;
 use std::sync::Mutex;
 
 lazy_static! {
     static ref LAMP_MODE_START: Mutex<Option<Instant>> = Mutex::new(None);
+    static ref DATA_PATHS: DataPaths = DataPaths::from_args_and_env();
 }
 
 fn send_lamp() -> bool {
@@ -75,28 +119,299 @@ fn reset_lamp_mode() {
 struct Message {
     id: String,
     text: String,
+    // Missing code is filled in by the stale re-encode on startup
+    #[serde(default)]
     morse_code: String,
     created_at: DateTime<Utc>,
+    #[serde(default)]
     last_sent: Option<DateTime<Utc>>,
+    #[serde(default)]
     send_count: u32,
+    #[serde(default)]
+    unsupported_chars: Vec<UnsupportedChar>,
+    // Name of the code table used for encoding; None means the standard encoder
+    #[serde(default)]
+    alphabet: Option<String>,
+    // Converter fingerprint the morse code was produced with; empty for old messages
+    #[serde(default)]
+    encoded_with: String,
+    #[serde(default)]
+    status: MessageStatus,
+    // Why the content filter held a submission for moderation
+    #[serde(default)]
+    content_flags: Vec<ContentFlag>,
+    // Tags, author, priority, language and curator notes
+    #[serde(flatten)]
+    meta: MessageMeta,
+    // Enabled flag, active window and play limit
+    #[serde(flatten)]
+    lifecycle: Lifecycle,
+    // Set while the message is in the trash
+    #[serde(default)]
+    deleted_at: Option<DateTime<Utc>>,
+}
+
+impl Message {
+    /// A message in the trash is kept for restoring but otherwise treated
+    /// as deleted.
+    fn in_trash(&self) -> bool {
+        self.deleted_at.is_some()
+    }
+}
+
+/// Number of messages outside the trash
+fn live_count(messages: &HashMap<String, Message>) -> usize {
+    messages.values().filter(|m| !m.in_trash()).count()
+}
+
+/// Moderation state of a message. Only approved messages are played; visitor
+/// submissions start as pending.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum MessageStatus {
+    #[default]
+    Approved,
+    Pending,
+    Rejected,
+}
+
+#[derive(Debug, Serialize)]
+struct ReencodedMessage {
+    id: String,
+    text: String,
+    old_morse_code: String,
+    new_morse_code: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CreatePresetRequest {
+    name: String,
+    // Omitted saves the config currently in use
+    #[serde(default)]
+    config: Option<serde_json::Value>,
+    #[serde(default)]
+    overwrite: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct DuplicatePresetRequest {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateScheduleRequest {
+    preset: String,
+    // Local time of day, "HH:MM", for a daily change
+    #[serde(default)]
+    daily_at: Option<String>,
+    // Instant of a one-off change
+    #[serde(default)]
+    at: Option<DateTime<Utc>>,
+}
+
+/// `/api/config` reply: the config plus the preset it was applied from and
+/// any change waiting for the next message
+#[derive(Serialize)]
+struct ConfigResponse<'a> {
+    #[serde(flatten)]
+    config: &'a TransformerConfig,
+    active_preset: Option<String>,
+    pending_config: Option<TransformerConfig>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ApplyQuery {
+    #[serde(default)]
+    apply: ApplyMode,
+}
+
+#[derive(Debug, Deserialize)]
+struct HistoryQuery {
+    // RFC 3339 timestamps or YYYY-MM-DD dates (UTC); a date in `to` includes that whole day
+    from: Option<String>,
+    to: Option<String>,
+    #[serde(default)]
+    format: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ImportQuery {
+    // json, csv or text; without it the content type decides
+    format: Option<String>,
+    #[serde(default)]
+    dry_run: bool,
+    // Reject messages with characters that cannot be encoded
+    #[serde(default)]
+    strict: bool,
+    // Used for items that do not name their own alphabet
+    alphabet: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LoginRequest {
+    name: String,
+    password: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ExportQuery {
+    format: Option<String>,
+}
+
+/// How a call to `send_morse_to_serial` ended
+struct PlaybackResult {
+    frame_count: u32,
+    interrupted: Option<InterruptReason>,
+}
+
+impl PlaybackResult {
+    /// Whether any of the message reached the lamp. Without the serial port
+    /// nothing was sent, so the message was not played.
+    fn reached_lamp(&self) -> bool {
+        self.interrupted != Some(InterruptReason::SerialUnavailable) && self.frame_count > 0
+    }
+}
+
+/// Counts a performance against the message, retiring it at `max_sends`, if
+/// any of it reached the lamp. Returns whether it counted.
+fn count_play(message: &mut Message, playback: &PlaybackResult, now: DateTime<Utc>) -> bool {
+    if !playback.reached_lamp() {
+        return false;
+    }
+    message.last_sent = Some(now);
+    message.send_count += 1;
+    if message.lifecycle.max_sends == Some(message.send_count) {
+        println!(
+            "Message {} retired after {} plays",
+            message.id, message.send_count
+        );
+    }
+    true
 }
 
 #[derive(Debug, Deserialize)]
 struct CreateMessageRequest {
     text: String,
+    // Reject text containing characters that cannot be encoded instead of storing with warnings
+    #[serde(default)]
+    strict: bool,
+    #[serde(default)]
+    alphabet: Option<String>,
+    #[serde(flatten)]
+    meta: MetaChanges,
+    #[serde(flatten)]
+    lifecycle: LifecycleChanges,
 }
 
+/// Body of the public `POST /api/submissions`
 #[derive(Debug, Deserialize)]
-struct UpdateMessageRequest {
+struct SubmitMessageRequest {
+    text: String,
+    #[serde(default)]
+    alphabet: Option<String>,
+}
+
+/// Filters of `GET /api/messages`; all given ones must match
+#[derive(Debug, Deserialize)]
+struct MessageQuery {
+    // Searches text, author, notes and tags
+    q: Option<String>,
+    // Comma-separated; messages need every listed tag
+    tag: Option<String>,
+    author: Option<String>,
+    language: Option<String>,
+    min_priority: Option<u8>,
+    status: Option<MessageStatus>,
+    state: Option<LifecycleState>,
+    #[serde(default)]
+    sort: SortKey,
+    #[serde(default)]
+    order: SortOrder,
+    // Without a limit every matching message is returned
+    limit: Option<usize>,
+    // From the X-Next-Cursor header of the previous page
+    cursor: Option<String>,
+}
+
+/// Body of `POST /api/preview`
+#[derive(Debug, Deserialize)]
+struct PreviewRequest {
     text: String,
+    #[serde(default)]
+    alphabet: Option<String>,
+    // Defaults to the tempo currently playing
+    tempo_ms: Option<u64>,
+    // How many of the generated frames to return
+    frame_limit: Option<usize>,
+}
+
+const DEFAULT_PREVIEW_FRAMES: usize = 20;
+const MAX_PREVIEW_FRAMES: usize = 200;
+
+#[derive(Debug, Deserialize)]
+struct ModerationQuery {
+    // Defaults to the pending queue
+    #[serde(default)]
+    status: Option<MessageStatus>,
+}
+
+#[derive(Debug, Deserialize)]
+struct UpdateMessageRequest {
+    // Omitted keeps the current text
+    #[serde(default)]
+    text: Option<String>,
+    #[serde(default)]
+    strict: bool,
+    // Omitted keeps the current alphabet, an empty string selects the standard encoder
+    #[serde(default)]
+    alphabet: Option<String>,
+    // Omitted metadata and lifecycle fields are kept as well
+    #[serde(flatten)]
+    meta: MetaChanges,
+    #[serde(flatten)]
+    lifecycle: LifecycleChanges,
+}
+
+/// Layout of `transformer_config.json`: the config with its schema version
+#[derive(Serialize, Deserialize)]
+struct ConfigFile<C> {
+    version: u32,
+    #[serde(flatten)]
+    config: C,
 }
 
 type TempoStore = Arc<RwLock<u64>>;
 type MessageStore = Arc<RwLock<HashMap<String, Message>>>;
 type ConfigStore = Arc<RwLock<TransformerConfig>>;
+type StatusStore = Arc<RwLock<StorageStatus>>;
+type Repository = Arc<dyn MessageRepository>;
+type PresetStore = Arc<RwLock<PresetBook>>;
+// Config waiting for the current message to finish
+type PendingConfigStore = Arc<RwLock<Option<TransformerConfig>>>;
+
+/// Everything a config change touches, shared by the config endpoints and
+/// the schedulers
+#[derive(Clone)]
+struct ConfigRuntime {
+    config_store: ConfigStore,
+    pending: PendingConfigStore,
+    store: MessageStore,
+    repository: Repository,
+    morse_converter: Arc<MorseConverter>,
+    audit_log: Arc<ConfigAuditLog>,
+}
 
-const MESSAGES_FILE_PATH: &str = "messages.json";
-const CONFIG_FILE_PATH: &str = "transformer_config.json";
+/// How the persisted files were loaded at startup
+#[derive(Debug, Clone, Serialize)]
+struct StorageStatus {
+    backend: &'static str,
+    messages: LoadStatus,
+    config: LoadStatus,
+    presets: LoadStatus,
+    degraded: bool,
+    /// The last edit made to the files by another program
+    last_reload: Option<ReloadEvent>,
+}
 
 fn generate_random_tempo(tempo_choices: &[u64]) -> u64 {
     if tempo_choices.is_empty() {
@@ -106,115 +421,141 @@ fn generate_random_tempo(tempo_choices: &[u64]) -> u64 {
     *tempo_choices.choose(&mut rng).unwrap()
 }
 
-fn load_messages_from_file(file_path: &str) -> HashMap<String, Message> {
-    if !Path::new(file_path).exists() {
-        println!(
-            "Messages file {} not found, starting with empty message store",
-            file_path
-        );
-        return HashMap::new();
-    }
-
-    match fs::read_to_string(file_path) {
-        Ok(content) => {
-            if content.trim().is_empty() {
-                println!(
-                    "Messages file {} is empty, starting with empty message store",
-                    file_path
-                );
-                return HashMap::new();
-            }
-
-            match serde_json::from_str::<HashMap<String, Message>>(&content) {
-                Ok(messages) => {
-                    println!("Loaded {} messages from {}", messages.len(), file_path);
-                    messages
-                }
-                Err(e) => {
-                    eprintln!("Failed to parse messages from {}: {}", file_path, e);
-                    println!("Starting with empty message store");
-                    HashMap::new()
-                }
-            }
+fn load_config_from_file(file_path: &str) -> (TransformerConfig, LoadStatus) {
+    let (file, status) =
+        storage::load_with_fallback::<ConfigFile<TransformerConfig>>(file_path, &CONFIG_SCHEMA);
+    let config = file.map(|file| file.config);
+    match &status {
+        LoadStatus::Missing => {
+            println!("Config file {} not found, using default config", file_path)
         }
-        Err(e) => {
-            eprintln!("Failed to read messages file {}: {}", file_path, e);
-            println!("Starting with empty message store");
-            HashMap::new()
+        LoadStatus::Loaded => println!("Loaded config from {}", file_path),
+        _ if config.is_none() => println!("Using default config"),
+        _ => {}
+    }
+    let config = config.unwrap_or_default();
+    // Kept as is so no settings are lost; playback clamps values it cannot use
+    if let Err(errors) = config.validate() {
+        eprintln!("WARNING: Config in {} has invalid fields:", file_path);
+        for error in errors {
+            eprintln!("  {}: {}", error.field, error.message);
         }
     }
+    (config, status)
 }
 
-fn load_config_from_file(file_path: &str) -> TransformerConfig {
-    if !Path::new(file_path).exists() {
-        println!("Config file {} not found, using default config", file_path);
-        return TransformerConfig::default();
+/// Parses the contents of the config file, e.g. after it was edited by hand.
+fn parse_config(content: &str) -> Result<TransformerConfig, Vec<String>> {
+    let value = serde_json::from_str(content).map_err(|e| vec![e.to_string()])?;
+    let (mut value, _) = CONFIG_SCHEMA.migrate(value).map_err(|e| vec![e])?;
+    if let Some(fields) = value.as_object_mut() {
+        fields.remove("version");
     }
+    TransformerConfig::from_json(value).map_err(|errors| {
+        errors
+            .into_iter()
+            .map(|error| match error.field.as_str() {
+                "" => error.message,
+                field => format!("{}: {}", field, error.message),
+            })
+            .collect()
+    })
+}
 
-    match fs::read_to_string(file_path) {
-        Ok(content) => match serde_json::from_str::<TransformerConfig>(&content) {
-            Ok(config) => {
-                println!("Loaded config from {}", file_path);
-                config
-            }
-            Err(e) => {
-                eprintln!("Failed to parse config from {}: {}", file_path, e);
-                println!("Using default config");
-                TransformerConfig::default()
-            }
+fn save_config_to_file(config: &TransformerConfig, file_path: &str) {
+    // Leave the file alone, formatting included, when it already holds this config
+    if fs::read_to_string(file_path)
+        .ok()
+        .and_then(|content| parse_config(&content).ok())
+        .is_some_and(|saved| saved == *config)
+    {
+        return;
+    }
+    let file = ConfigFile {
+        version: CONFIG_SCHEMA.current_version(),
+        config,
+    };
+    match serde_json::to_string_pretty(&file) {
+        Ok(json_content) => match storage::save_with_snapshots(file_path, &json_content) {
+            Ok(_) => println!("Config saved to {}", file_path),
+            Err(e) => eprintln!("Failed to write config to {}: {}", file_path, e),
         },
-        Err(e) => {
-            eprintln!("Failed to read config file {}: {}", file_path, e);
-            println!("Using default config");
-            TransformerConfig::default()
-        }
+        Err(e) => eprintln!("Failed to serialize config: {}", e),
     }
 }
 
-fn save_messages_to_file(messages: &HashMap<String, Message>, file_path: &str) {
-    // Don't save if messages is empty and file already exists with content
-    if messages.is_empty() && Path::new(file_path).exists() {
-        if let Ok(content) = fs::read_to_string(file_path) {
-            if !content.trim().is_empty() && content.trim() != "{}" {
-                println!("WARNING: Skipping save of empty message store - file has existing data");
-                return;
+/// Recomputes the Morse code of every message (or only the stale ones, whose
+/// stored fingerprint no longer matches the converter) and reports the
+/// messages whose code changed.
+fn reencode_messages(
+    messages: &mut HashMap<String, Message>,
+    morse_converter: &MorseConverter,
+    newline_policy: NewlinePolicy,
+    only_stale: bool,
+) -> Vec<ReencodedMessage> {
+    let mut changed = Vec::new();
+    for message in messages.values_mut() {
+        if only_stale
+            && message.encoded_with
+                == morse_converter.fingerprint(message.alphabet.as_deref(), newline_policy)
+        {
+            continue;
+        }
+        match morse_converter.convert(&message.text, message.alphabet.as_deref(), newline_policy) {
+            Ok(conversion) => {
+                if conversion.morse_code != message.morse_code {
+                    changed.push(ReencodedMessage {
+                        id: message.id.clone(),
+                        text: message.text.clone(),
+                        old_morse_code: message.morse_code.clone(),
+                        new_morse_code: conversion.morse_code.clone(),
+                    });
+                }
+                message.morse_code = conversion.morse_code;
+                message.unsupported_chars = conversion.unsupported;
+                message.encoded_with = conversion.encoded_with;
             }
+            Err(e) => eprintln!("Failed to re-encode message {}: {}", message.id, e),
         }
     }
-
-    match serde_json::to_string_pretty(messages) {
-        Ok(json_content) => match fs::write(file_path, json_content) {
-            Ok(_) => println!(
-                "Messages saved to {} ({} messages)",
-                file_path,
-                messages.len()
-            ),
-            Err(e) => eprintln!("Failed to write messages to {}: {}", file_path, e),
-        },
-        Err(e) => eprintln!("Failed to serialize messages: {}", e),
-    }
+    changed
 }
 
-fn save_config_to_file(config: &TransformerConfig, file_path: &str) {
-    match serde_json::to_string_pretty(config) {
-        Ok(json_content) => match fs::write(file_path, json_content) {
-            Ok(_) => println!("Config saved to {}", file_path),
-            Err(e) => eprintln!("Failed to write config to {}: {}", file_path, e),
-        },
-        Err(e) => eprintln!("Failed to serialize config: {}", e),
-    }
+/// Saves the config that runs once any pending change applies. A reloaded
+/// hand edit waits in `pending`, and saving the running config instead would
+/// overwrite the edit on disk.
+fn save_latest_config(config_store: &ConfigStore, pending: &PendingConfigStore, file_path: &str) {
+    let config = pending
+        .read()
+        .clone()
+        .unwrap_or_else(|| config_store.read().clone());
+    save_config_to_file(&config, file_path);
 }
 
-fn start_auto_save_scheduler(message_store: MessageStore, config_store: ConfigStore) {
+fn start_auto_save_scheduler(
+    message_store: MessageStore,
+    repository: Repository,
+    config_store: ConfigStore,
+    pending: PendingConfigStore,
+    trash_retention_days: u32,
+) {
     thread::spawn(move || {
         let mut scheduler = Scheduler::new();
 
+        if trash_retention_days > 0 {
+            let message_store = message_store.clone();
+            let repository = repository.clone();
+            purge_expired_trash(&message_store, &repository, trash_retention_days);
+            scheduler.every(1.hour()).run(move || {
+                purge_expired_trash(&message_store, &repository, trash_retention_days);
+            });
+        }
+
         scheduler.every(30.seconds()).run(move || {
             let messages = message_store.read();
-            save_messages_to_file(&messages, MESSAGES_FILE_PATH);
+            log_write_error(repository.flush(&messages));
 
-            let config = config_store.read();
-            save_config_to_file(&config, CONFIG_FILE_PATH);
+            save_latest_config(&config_store, &pending, &DATA_PATHS.config_file);
         });
 
         loop {
@@ -224,56 +565,480 @@ fn start_auto_save_scheduler(message_store: MessageStore, config_store: ConfigSt
     });
 }
 
+/// Polls the config file and, with JSON storage, the messages file for edits
+/// made by other programs and merges them into the running state.
+fn start_file_watcher(
+    runtime: ConfigRuntime,
+    preset_store: PresetStore,
+    status_store: StatusStore,
+) {
+    let normalize_config: hot_reload::Normalize = |content| {
+        parse_config(content)
+            .ok()
+            .and_then(|config| serde_json::to_value(config).ok())
+    };
+    let normalize_messages: hot_reload::Normalize = |content| {
+        parse_messages(content)
+            .ok()
+            .and_then(|messages| serde_json::to_value(messages).ok())
+    };
+    let mut config_file = WatchedFile::new(&DATA_PATHS.config_file, normalize_config);
+    // SQLite storage only reads messages.json once, for the initial import
+    let mut messages_file = matches!(DATA_PATHS.storage, StorageBackend::Json)
+        .then(|| WatchedFile::new(&DATA_PATHS.messages_file, normalize_messages));
+
+    thread::spawn(move || {
+        let mut scheduler = Scheduler::new();
+
+        scheduler.every(2.seconds()).run(move || {
+            let mut events = Vec::new();
+            if let Some(content) = config_file.poll() {
+                let base = config_file.base().clone();
+                config_file.accept(&content);
+                events.push(reload_config(&content, &base, &runtime, &preset_store));
+            }
+            if let Some(messages_file) = messages_file.as_mut()
+                && let Some(content) = messages_file.poll()
+            {
+                let base = messages_file.base().clone();
+                messages_file.accept(&content);
+                events.push(reload_messages(&content, &base, &runtime));
+            }
+
+            for event in events {
+                if !event.applied {
+                    eprintln!(
+                        "Rejected external edit of {}; keeping the running state:",
+                        event.file
+                    );
+                    for error in &event.errors {
+                        eprintln!("  {}", error);
+                    }
+                } else if !event.conflicts.is_empty() {
+                    eprintln!(
+                        "WARNING: {} and unsaved changes both edited {}; kept the file's values",
+                        event.file,
+                        event.conflicts.join(", ")
+                    );
+                }
+                status_store.write().last_reload = Some(event);
+            }
+        });
+
+        loop {
+            scheduler.run_pending();
+            thread::sleep(Duration::from_millis(500));
+        }
+    });
+}
+
+/// Merges a hand-edited config file with changes not yet saved and applies
+/// the result once the current message has finished.
+fn reload_config(
+    content: &str,
+    base: &serde_json::Value,
+    runtime: &ConfigRuntime,
+    preset_store: &PresetStore,
+) -> ReloadEvent {
+    let file = &DATA_PATHS.config_file;
+    let disk = match parse_config(content) {
+        Ok(config) => config,
+        Err(errors) => return ReloadEvent::rejected(file, errors),
+    };
+    let memory = runtime
+        .pending
+        .read()
+        .clone()
+        .unwrap_or_else(|| runtime.config_store.read().clone());
+
+    let mut conflicts = Vec::new();
+    let merged = hot_reload::merge3(
+        base,
+        &serde_json::to_value(&disk).unwrap_or_default(),
+        &serde_json::to_value(&memory).unwrap_or_default(),
+        &mut conflicts,
+    );
+    // The merge can combine individually valid fields into an invalid config
+    let merged = match TransformerConfig::from_json(merged) {
+        Ok(config) => config,
+        Err(errors) => {
+            let errors = errors
+                .into_iter()
+                .map(|error| format!("{}: {}", error.field, error.message))
+                .collect();
+            return ReloadEvent::rejected(file, errors);
+        }
+    };
+
+    println!("Reloading config edited in {}", file);
+    let mut presets = preset_store.write();
+    if presets.note_config_change(&merged) {
+        presets::save_presets(&presets, &DATA_PATHS.presets_file);
+    }
+    drop(presets);
+    change_config(merged, "disk", "file", ApplyMode::NextMessage, runtime);
+    ReloadEvent::applied(file, conflicts)
+}
+
+/// Merges a hand-edited messages file into the message store, re-encoding
+/// messages whose text changed.
+fn reload_messages(
+    content: &str,
+    base: &serde_json::Value,
+    runtime: &ConfigRuntime,
+) -> ReloadEvent {
+    let file = &DATA_PATHS.messages_file;
+    let disk = match parse_messages(content) {
+        Ok(messages) => messages,
+        Err(e) => return ReloadEvent::rejected(file, vec![e]),
+    };
+    let errors: Vec<String> = disk
+        .iter()
+        .filter_map(|(key, message)| {
+            if *key != message.id {
+                Some(format!("{}: id {} does not match its key", key, message.id))
+            } else if message.text.trim().is_empty() {
+                Some(format!("{}: text must not be empty", key))
+            } else {
+                None
+            }
+        })
+        .collect();
+    if !errors.is_empty() {
+        return ReloadEvent::rejected(file, errors);
+    }
+
+    let mut messages = runtime.store.write();
+    let disk_value = serde_json::to_value(&disk).unwrap_or_default();
+    let mut conflicts = Vec::new();
+    let merged = hot_reload::merge3(
+        base,
+        &disk_value,
+        &serde_json::to_value(&*messages).unwrap_or_default(),
+        &mut conflicts,
+    );
+    let mut merged: HashMap<String, Message> = match serde_json::from_value(merged) {
+        Ok(merged) => merged,
+        Err(e) => return ReloadEvent::rejected(file, vec![e.to_string()]),
+    };
+
+    for message in merged.values_mut() {
+        let edited = messages
+            .get(&message.id)
+            .is_none_or(|old| old.text != message.text || old.alphabet != message.alphabet);
+        if edited {
+            // Marks the code stale so it is re-encoded below
+            message.encoded_with.clear();
+        }
+    }
+    let newline_policy = runtime.config_store.read().newline_policy;
+    reencode_messages(&mut merged, &runtime.morse_converter, newline_policy, true);
+
+    println!(
+        "Reloaded {} messages edited in {} ({} before)",
+        merged.len(),
+        file,
+        messages.len()
+    );
+    *messages = merged;
+    // Write back unsaved changes and new Morse code the edit lacked
+    if serde_json::to_value(&*messages).unwrap_or_default() != disk_value {
+        log_write_error(runtime.repository.replace_all(&messages));
+    }
+    ReloadEvent::applied(file, conflicts)
+}
+
+/// Applies scheduled preset changes as their time comes.
+fn start_preset_scheduler(preset_store: PresetStore, runtime: ConfigRuntime) {
+    thread::spawn(move || {
+        let mut scheduler = Scheduler::new();
+        let mut last_check = Local::now();
+
+        scheduler.every(15.seconds()).run(move || {
+            let now = Local::now();
+            let due = {
+                let mut presets = preset_store.write();
+                let scheduled = presets.schedule.len();
+                let due = presets.take_due(last_check, now);
+                // Fired one-offs are gone; keep them from firing again after a restart
+                if presets.schedule.len() != scheduled {
+                    presets::save_presets(&presets, &DATA_PATHS.presets_file);
+                }
+                due
+            };
+            last_check = now;
+
+            if let Some(name) = due {
+                println!("Scheduled switch to config preset '{}'", name);
+                if let Err(e) = apply_preset_by_name(
+                    &name,
+                    "scheduler",
+                    ApplyMode::NextMessage,
+                    &preset_store,
+                    &runtime,
+                ) {
+                    eprintln!("Failed to apply scheduled preset: {}", e);
+                }
+            }
+        });
+
+        loop {
+            scheduler.run_pending();
+            thread::sleep(Duration::from_millis(1000));
+        }
+    });
+}
+
+/// Opens the message storage backend selected with `--storage`.
+fn open_repository() -> Repository {
+    match DATA_PATHS.storage {
+        StorageBackend::Json => Arc::new(JsonMessageRepository::new(&DATA_PATHS.messages_file)),
+        #[cfg(feature = "sqlite")]
+        StorageBackend::Sqlite => {
+            let repository = match SqliteMessageRepository::open(&DATA_PATHS.database_file) {
+                Ok(repository) => repository,
+                Err(e) => {
+                    eprintln!(
+                        "Failed to open SQLite database {}: {}",
+                        DATA_PATHS.database_file, e
+                    );
+                    std::process::exit(1);
+                }
+            };
+            if let Err(e) = repository.import_json_if_empty(&DATA_PATHS.messages_file) {
+                eprintln!("Failed to import {}: {}", DATA_PATHS.messages_file, e);
+            }
+            Arc::new(repository)
+        }
+        #[cfg(not(feature = "sqlite"))]
+        StorageBackend::Sqlite => {
+            eprintln!(
+                "Cannot open {}: SQLite storage requires building with --features sqlite",
+                DATA_PATHS.database_file
+            );
+            std::process::exit(2);
+        }
+    }
+}
+
 #[tokio::main]
 async fn main() {
-    let initial_messages = load_messages_from_file(MESSAGES_FILE_PATH);
+    if std::env::args().nth(1).as_deref() == Some("hash-password") {
+        print_password_hash();
+        return;
+    }
+    lazy_static::initialize(&DATA_PATHS);
+    if let Err(e) = fs::create_dir_all(&DATA_PATHS.data_dir) {
+        eprintln!(
+            "Failed to create data directory {}: {}",
+            DATA_PATHS.data_dir.display(),
+            e
+        );
+    }
+    println!("Using data directory {}", DATA_PATHS.data_dir.display());
+
+    let auth = match Auth::load(&DATA_PATHS.auth_file) {
+        Ok(auth) => Arc::new(auth),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    if !auth.enabled() {
+        eprintln!(
+            "WARNING: {} not found - the management API is open to everyone",
+            DATA_PATHS.auth_file
+        );
+    }
+    let content_filter = match ContentFilter::load(&DATA_PATHS.content_filter_file) {
+        Ok(filter) => Arc::new(filter),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+    let limits = match Limits::load(&DATA_PATHS.limits_file) {
+        Ok(limits) => Arc::new(limits),
+        Err(e) => {
+            eprintln!("{}", e);
+            std::process::exit(2);
+        }
+    };
+
+    let repository = open_repository();
+    let (initial_messages, messages_status) = repository.load_all();
     let message_store: MessageStore = Arc::new(RwLock::new(initial_messages));
 
-    let initial_config = load_config_from_file(CONFIG_FILE_PATH);
+    let (initial_config, config_status) = load_config_from_file(&DATA_PATHS.config_file);
     let config_store: ConfigStore = Arc::new(RwLock::new(initial_config.clone()));
 
-    let morse_converter = Arc::new(MorseConverter {});
+    let (initial_presets, presets_status) = presets::load_presets(&DATA_PATHS.presets_file);
+    let preset_store: PresetStore = Arc::new(RwLock::new(initial_presets));
+
+    let status_store: StatusStore = Arc::new(RwLock::new(StorageStatus {
+        backend: repository.name(),
+        degraded: messages_status.is_degraded()
+            || config_status.is_degraded()
+            || presets_status.is_degraded(),
+        messages: messages_status,
+        config: config_status,
+        presets: presets_status,
+        last_reload: None,
+    }));
+
+    let morse_converter = Arc::new(MorseConverter::new(CodeTableRegistry::load(
+        &DATA_PATHS.code_tables_dir,
+    )));
+
+    // Re-encode messages stored by an older converter version or with other options
+    let changed = reencode_messages(
+        &mut message_store.write(),
+        &morse_converter,
+        initial_config.newline_policy,
+        true,
+    );
+    if !changed.is_empty() {
+        println!("Re-encoded {} stale messages on startup", changed.len());
+        for message in &changed {
+            println!("  {}: {}", message.id, message.text);
+        }
+        log_write_error(repository.replace_all(&message_store.read()));
+    }
+    let play_log = Arc::new(PlayLog::new(&DATA_PATHS.history_file));
+
+    let audit_log = Arc::new(ConfigAuditLog::open(&DATA_PATHS.audit_file));
+    if let Err(e) = audit_log.record_startup(&initial_config) {
+        eprintln!("Failed to write config audit log: {}", e);
+    }
+    let config_runtime = ConfigRuntime {
+        config_store: config_store.clone(),
+        pending: Arc::new(RwLock::new(None)),
+        store: message_store.clone(),
+        repository: repository.clone(),
+        morse_converter: morse_converter.clone(),
+        audit_log,
+    };
+
     let initial_tempo = generate_random_tempo(&initial_config.tempo_choices);
     let tempo_store: TempoStore = Arc::new(RwLock::new(initial_tempo));
 
     println!("Initial tempo: {} ms", initial_tempo);
 
-    start_auto_save_scheduler(message_store.clone(), config_store.clone());
-
-    let store_clone = message_store.clone();
-    let converter_clone = morse_converter.clone();
+    start_auto_save_scheduler(
+        message_store.clone(),
+        repository.clone(),
+        config_store.clone(),
+        config_runtime.pending.clone(),
+        limits.config.trash_retention_days,
+    );
+
+    start_preset_scheduler(preset_store.clone(), config_runtime.clone());
+    start_file_watcher(
+        config_runtime.clone(),
+        preset_store.clone(),
+        status_store.clone(),
+    );
+
+    let runtime_clone = config_runtime.clone();
+    let presets_clone = preset_store.clone();
     let tempo_clone = tempo_store.clone();
-    let config_clone = config_store.clone();
+    let play_log_clone = play_log.clone();
     thread::spawn(move || {
-        start_message_scheduler(store_clone, converter_clone, tempo_clone, config_clone);
+        start_message_scheduler(runtime_clone, presets_clone, play_log_clone, tempo_clone);
     });
 
-    let cors = warp::cors()
-        .allow_any_origin()
-        .allow_headers(vec!["content-type"])
-        .allow_methods(vec!["GET", "POST", "PUT", "DELETE"]);
+    let curator = require_role(auth.clone(), Role::Curator);
+    let operator = require_role(auth.clone(), Role::Operator);
+    let write_limit = with_rate_limit(limits.clone(), LimitKind::Write);
+    let json_body_limit = warp::body::content_length_limit(limits.config.max_body_bytes);
 
     let messages_store = message_store.clone();
     let morse_clone = morse_converter.clone();
 
-    let static_files = warp::path("static").and(warp::fs::dir("static"));
-    let index = warp::path::end().map(|| warp::reply::html(include_str!("../static/index.html")));
+    let static_files = warp::path("static").and(warp::fs::dir(DATA_PATHS.static_dir.clone()));
+    // The page in the static directory wins, so it can be changed without a
+    // rebuild; the built-in copy covers a missing file
+    let index = warp::path::end()
+        .and(warp::fs::file(
+            std::path::Path::new(&DATA_PATHS.static_dir).join("index.html"),
+        ))
+        .or(warp::path::end().map(|| warp::reply::html(include_str!("../static/index.html"))));
     let api = warp::path("api");
 
+    let login = api
+        .and(warp::path("auth"))
+        .and(warp::path("login"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(write_limit.clone())
+        .and(json_body_limit)
+        .and(warp::body::json())
+        .and(with_auth(auth.clone()))
+        .and_then(log_in);
+
+    let logout = api
+        .and(warp::path("auth"))
+        .and(warp::path("logout"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(write_limit.clone())
+        .and(warp::cookie::optional::<String>(SESSION_COOKIE))
+        .and(warp::header::optional::<String>("authorization"))
+        .and(with_auth(auth.clone()))
+        .and_then(log_out);
+
+    let whoami = api
+        .and(warp::path("auth"))
+        .and(warp::path("me"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(with_principal(auth.clone(), Role::Curator))
+        .and(with_auth(auth.clone()))
+        .and_then(get_current_user);
+
     let get_messages = api
         .and(warp::path("messages"))
         .and(warp::path::end())
         .and(warp::get())
+        .and(curator.clone())
+        .and(warp::query::<MessageQuery>())
         .and(with_store(messages_store.clone()))
         .and_then(get_all_messages);
 
+    let get_single_message = api
+        .and(warp::path("messages"))
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(curator.clone())
+        .and(with_store(messages_store.clone()))
+        .and_then(get_message);
+
+    let preview = api
+        .and(warp::path("preview"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(curator.clone())
+        .and(json_body_limit)
+        .and(warp::body::json())
+        .and(with_morse_converter(morse_clone.clone()))
+        .and(with_config_store(config_store.clone()))
+        .and(with_tempo_store(tempo_store.clone()))
+        .and_then(preview_message);
+
     let create_message = api
         .and(warp::path("messages"))
         .and(warp::path::end())
         .and(warp::post())
+        .and(curator.clone())
+        .and(write_limit.clone())
+        .and(json_body_limit)
         .and(warp::body::json())
         .and(with_store(messages_store.clone()))
+        .and(with_repository(repository.clone()))
         .and(with_morse_converter(morse_clone.clone()))
+        .and(with_config_store(config_store.clone()))
+        .and(with_limits(limits.clone()))
         .and_then(create_new_message);
 
     let update_message = api
@@ -281,9 +1046,15 @@ async fn main() {
         .and(warp::path::param::<String>())
         .and(warp::path::end())
         .and(warp::put())
+        .and(curator.clone())
+        .and(write_limit.clone())
+        .and(json_body_limit)
         .and(warp::body::json())
         .and(with_store(messages_store.clone()))
+        .and(with_repository(repository.clone()))
         .and(with_morse_converter(morse_clone.clone()))
+        .and(with_config_store(config_store.clone()))
+        .and(with_content_filter(content_filter.clone()))
         .and_then(update_existing_message);
 
     let delete_message = api
@@ -291,196 +1062,1917 @@ async fn main() {
         .and(warp::path::param::<String>())
         .and(warp::path::end())
         .and(warp::delete())
+        .and(curator.clone())
+        .and(write_limit.clone())
         .and(with_store(messages_store.clone()))
+        .and(with_repository(repository.clone()))
         .and_then(delete_existing_message);
 
-    let get_tempo = api
-        .and(warp::path("tempo"))
+    let trash_path = api.and(warp::path("trash"));
+
+    let get_trash_route = trash_path
         .and(warp::path::end())
         .and(warp::get())
-        .and(with_tempo_store(tempo_store.clone()))
-        .and_then(get_current_tempo);
+        .and(curator.clone())
+        .and(with_store(messages_store.clone()))
+        .and_then(get_trash);
+
+    let restore_message_route = trash_path
+        .and(warp::path::param::<String>())
+        .and(warp::path("restore"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(curator.clone())
+        .and(write_limit.clone())
+        .and(with_store(messages_store.clone()))
+        .and(with_repository(repository.clone()))
+        .and(with_limits(limits.clone()))
+        .and_then(restore_message);
+
+    let purge_message_route = trash_path
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(curator.clone())
+        .and(write_limit.clone())
+        .and(with_store(messages_store.clone()))
+        .and(with_repository(repository.clone()))
+        .and_then(purge_message);
+
+    let empty_trash_route = trash_path
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(curator.clone())
+        .and(write_limit.clone())
+        .and(with_store(messages_store.clone()))
+        .and(with_repository(repository.clone()))
+        .and_then(empty_trash);
+
+    let submit_message_route = api
+        .and(warp::path("submissions"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(with_rate_limit(limits.clone(), LimitKind::Submission))
+        .and(json_body_limit)
+        .and(warp::body::json())
+        .and(with_store(messages_store.clone()))
+        .and(with_repository(repository.clone()))
+        .and(with_morse_converter(morse_clone.clone()))
+        .and(with_config_store(config_store.clone()))
+        .and(with_content_filter(content_filter.clone()))
+        .and(with_limits(limits.clone()))
+        .and_then(submit_message);
+
+    let moderation_path = api.and(warp::path("moderation"));
+
+    let get_moderation = moderation_path
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(curator.clone())
+        .and(warp::query::<ModerationQuery>())
+        .and(with_store(messages_store.clone()))
+        .and_then(get_moderation_queue);
+
+    let approve_message = moderation_path
+        .and(warp::path::param::<String>())
+        .and(warp::path("approve"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .map(|id| (id, MessageStatus::Approved))
+        .untuple_one();
+    let reject_message = moderation_path
+        .and(warp::path::param::<String>())
+        .and(warp::path("reject"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .map(|id| (id, MessageStatus::Rejected))
+        .untuple_one();
+    let moderate = approve_message
+        .or(reject_message)
+        .unify()
+        .and(with_actor(auth.clone(), Role::Curator))
+        .and(write_limit.clone())
+        .and(with_store(messages_store.clone()))
+        .and(with_repository(repository.clone()))
+        .and_then(moderate_message);
+
+    let get_alphabets = api
+        .and(warp::path("alphabets"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(curator.clone())
+        .and(with_morse_converter(morse_clone.clone()))
+        .and_then(get_available_alphabets);
+
+    let get_tempo = api
+        .and(warp::path("tempo"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(curator.clone())
+        .and(with_tempo_store(tempo_store.clone()))
+        .and_then(get_current_tempo);
+
+    let save_messages = api
+        .and(warp::path("messages"))
+        .and(warp::path("save"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(curator.clone())
+        .and(write_limit.clone())
+        .and(with_store(messages_store.clone()))
+        .and(with_repository(repository.clone()))
+        .and_then(save_messages_manually);
+
+    let import_messages_route = api
+        .and(warp::path("messages"))
+        .and(warp::path("import"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(curator.clone())
+        .and(write_limit.clone())
+        .and(warp::query::<ImportQuery>())
+        .and(warp::header::optional::<String>("content-type"))
+        .and(warp::body::content_length_limit(
+            limits.config.max_import_bytes,
+        ))
+        .and(warp::body::bytes())
+        .and(with_config_runtime(config_runtime.clone()))
+        .and(with_limits(limits.clone()))
+        .and_then(import_messages);
+
+    let export_messages_route = api
+        .and(warp::path("messages"))
+        .and(warp::path("export"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(curator.clone())
+        .and(warp::query::<ExportQuery>())
+        .and(with_store(messages_store.clone()))
+        .and_then(export_messages);
+
+    let reencode_messages_route = api
+        .and(warp::path("messages"))
+        .and(warp::path("reencode"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(curator.clone())
+        .and(write_limit.clone())
+        .and(with_store(messages_store.clone()))
+        .and(with_repository(repository.clone()))
+        .and(with_morse_converter(morse_clone.clone()))
+        .and(with_config_store(config_store.clone()))
+        .and_then(reencode_all_messages);
+
+    let get_status = api
+        .and(warp::path("status"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(curator.clone())
+        .and(with_status_store(status_store.clone()))
+        .and_then(get_storage_status);
+
+    let get_config = api
+        .and(warp::path("config"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(operator.clone())
+        .and(with_config_runtime(config_runtime.clone()))
+        .and(with_preset_store(preset_store.clone()))
+        .and_then(get_transformer_config);
+
+    let update_config = api
+        .and(warp::path("config"))
+        .and(warp::path::end())
+        .and(warp::put())
+        .and(warp::query::<ApplyQuery>())
+        .and(with_actor(auth.clone(), Role::Operator))
+        .and(write_limit.clone())
+        .and(json_body_limit)
+        .and(warp::body::json())
+        .and(with_config_runtime(config_runtime.clone()))
+        .and(with_preset_store(preset_store.clone()))
+        .and_then(update_transformer_config);
+
+    let patch_config = api
+        .and(warp::path("config"))
+        .and(warp::path::end())
+        .and(warp::patch())
+        .and(warp::query::<ApplyQuery>())
+        .and(with_actor(auth.clone(), Role::Operator))
+        .and(write_limit.clone())
+        // Raw body so `application/merge-patch+json` is accepted too
+        .and(json_body_limit)
+        .and(warp::body::bytes())
+        .and(with_config_runtime(config_runtime.clone()))
+        .and(with_preset_store(preset_store.clone()))
+        .and_then(patch_transformer_config);
+
+    let get_config_audit = api
+        .and(warp::path("config"))
+        .and(warp::path("audit"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(operator.clone())
+        .and(with_config_runtime(config_runtime.clone()))
+        .and_then(get_config_audit_log);
+
+    let revert_config = api
+        .and(warp::path("config"))
+        .and(warp::path("audit"))
+        .and(warp::path::param::<u64>())
+        .and(warp::path("revert"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::query::<ApplyQuery>())
+        .and(with_actor(auth.clone(), Role::Operator))
+        .and(write_limit.clone())
+        .and(with_config_runtime(config_runtime.clone()))
+        .and(with_preset_store(preset_store.clone()))
+        .and_then(revert_config_version);
+
+    let presets_path = api.and(warp::path("config")).and(warp::path("presets"));
+
+    let list_presets = presets_path
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(operator.clone())
+        .and(with_preset_store(preset_store.clone()))
+        .and_then(list_config_presets);
+
+    let create_preset = presets_path
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(operator.clone())
+        .and(write_limit.clone())
+        .and(json_body_limit)
+        .and(warp::body::json())
+        .and(with_preset_store(preset_store.clone()))
+        .and(with_config_store(config_store.clone()))
+        .and_then(create_config_preset);
+
+    let apply_preset = presets_path
+        .and(warp::path::param::<String>())
+        .and(warp::path("apply"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(warp::query::<ApplyQuery>())
+        .and(with_actor(auth.clone(), Role::Operator))
+        .and(write_limit.clone())
+        .and(with_preset_store(preset_store.clone()))
+        .and(with_config_runtime(config_runtime.clone()))
+        .and_then(apply_config_preset);
+
+    let duplicate_preset = presets_path
+        .and(warp::path::param::<String>())
+        .and(warp::path("duplicate"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(operator.clone())
+        .and(write_limit.clone())
+        .and(json_body_limit)
+        .and(warp::body::json())
+        .and(with_preset_store(preset_store.clone()))
+        .and_then(duplicate_config_preset);
+
+    let delete_preset = presets_path
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(operator.clone())
+        .and(write_limit.clone())
+        .and(with_preset_store(preset_store.clone()))
+        .and_then(delete_config_preset);
+
+    let schedule_path = api.and(warp::path("config")).and(warp::path("schedule"));
+
+    let get_schedule = schedule_path
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(operator.clone())
+        .and(with_preset_store(preset_store.clone()))
+        .and_then(get_preset_schedule);
+
+    let create_schedule = schedule_path
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(operator.clone())
+        .and(write_limit.clone())
+        .and(json_body_limit)
+        .and(warp::body::json())
+        .and(with_preset_store(preset_store.clone()))
+        .and_then(create_schedule_entry);
+
+    let delete_schedule = schedule_path
+        .and(warp::path::param::<String>())
+        .and(warp::path::end())
+        .and(warp::delete())
+        .and(operator.clone())
+        .and(write_limit.clone())
+        .and(with_preset_store(preset_store.clone()))
+        .and_then(delete_schedule_entry);
+
+    let get_history = api
+        .and(warp::path("history"))
+        .and(warp::path::end())
+        .and(warp::get())
+        .and(curator.clone())
+        .and(warp::query::<HistoryQuery>())
+        .and(with_play_log(play_log.clone()))
+        .and_then(get_play_history);
+
+    let skip_message = api
+        .and(warp::path("playback"))
+        .and(warp::path("skip"))
+        .and(warp::path::end())
+        .and(warp::post())
+        .and(operator.clone())
+        .and(write_limit.clone())
+        .and_then(skip_current_message);
+
+    let routes = index
+        .or(static_files)
+        .or(get_messages)
+        .or(create_message)
+        .or(update_message)
+        .or(delete_message)
+        .or(get_trash_route)
+        .or(restore_message_route)
+        .or(purge_message_route)
+        .or(empty_trash_route)
+        .or(submit_message_route)
+        .or(get_moderation)
+        .or(moderate)
+        .or(get_alphabets)
+        .or(get_tempo)
+        .or(save_messages)
+        .or(reencode_messages_route)
+        .or(import_messages_route)
+        .or(export_messages_route)
+        // After the fixed paths under /api/messages so "export" is no id
+        .or(get_single_message)
+        .or(preview)
+        .or(get_status)
+        .or(get_config)
+        .or(update_config)
+        .or(patch_config)
+        .or(get_config_audit)
+        .or(revert_config)
+        .or(list_presets)
+        .or(create_preset)
+        .or(apply_preset)
+        .or(duplicate_preset)
+        .or(delete_preset)
+        .or(get_schedule)
+        .or(create_schedule)
+        .or(delete_schedule)
+        .or(get_history)
+        .or(skip_message)
+        .or(login)
+        .or(logout)
+        .or(whoami)
+        .recover(api_error::handle_rejection);
+
+    let shutdown_store = message_store.clone();
+    let shutdown_repository = repository.clone();
+    let shutdown_config = config_store.clone();
+    let shutdown_pending = config_runtime.pending.clone();
+    ctrlc::set_handler(move || {
+        println!("Received Ctrl+C, saving messages and config before shutdown...");
+        let messages = shutdown_store.read();
+        log_write_error(shutdown_repository.flush(&messages));
+        save_latest_config(&shutdown_config, &shutdown_pending, &DATA_PATHS.config_file);
+        std::process::exit(0);
+    })
+    .expect("Error setting Ctrl-C handler");
+
+    println!("Morse Code Web API running on http://localhost:3030");
+    match cors_for(auth.cors_origins()) {
+        Some(cors) => {
+            warp::serve(routes.with(cors))
+                .run(([0, 0, 0, 0], 3030))
+                .await
+        }
+        None => warp::serve(routes).run(([0, 0, 0, 0], 3030)).await,
+    }
+}
+
+fn with_store(
+    store: MessageStore,
+) -> impl Filter<Extract = (MessageStore,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || store.clone())
+}
+
+fn with_repository(
+    repository: Repository,
+) -> impl Filter<Extract = (Repository,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || repository.clone())
+}
+
+fn with_config_runtime(
+    runtime: ConfigRuntime,
+) -> impl Filter<Extract = (ConfigRuntime,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || runtime.clone())
+}
+
+fn with_auth(
+    auth: Arc<Auth>,
+) -> impl Filter<Extract = (Arc<Auth>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || auth.clone())
+}
+
+/// Session token from an `Authorization: Bearer` header or the session cookie
+fn request_token(cookie: Option<String>, authorization: Option<String>) -> Option<String> {
+    authorization
+        .as_deref()
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string())
+        .or(cookie)
+}
+
+/// Lets the request through when its session has at least `role`, yielding
+/// who made it. With authentication disabled everyone passes as an operator,
+/// named by the client address. A name from the `X-Operator` header is
+/// recorded too, but marked unverified since any client can send one.
+fn with_principal(
+    auth: Arc<Auth>,
+    role: Role,
+) -> impl Filter<Extract = (Principal,), Error = warp::Rejection> + Clone {
+    warp::cookie::optional::<String>(SESSION_COOKIE)
+        .and(warp::header::optional::<String>("authorization"))
+        .and(warp::header::optional::<String>("x-operator"))
+        .and(warp::addr::remote())
+        .and_then(
+            move |cookie: Option<String>,
+                  authorization: Option<String>,
+                  operator: Option<String>,
+                  addr: Option<SocketAddr>| {
+                let auth = auth.clone();
+                async move {
+                    if !auth.enabled() {
+                        let address = addr
+                            .map(|addr| addr.ip().to_string())
+                            .unwrap_or_else(|| "unknown".to_string());
+                        let claimed: Option<String> = operator
+                            .map(|name| name.trim().chars().take(64).collect())
+                            .filter(|name: &String| !name.is_empty());
+                        let name = match claimed {
+                            Some(claimed) => format!("{claimed} (unverified, {address})"),
+                            None => address,
+                        };
+                        return Ok(Principal {
+                            name,
+                            role: Role::Operator,
+                        });
+                    }
+                    let session = request_token(cookie, authorization)
+                        .and_then(|token| auth.session(&token))
+                        .ok_or(warp::reject::custom(ApiError::Unauthorized))?;
+                    if session.principal.role < role {
+                        return Err(warp::reject::custom(ApiError::Forbidden(role)));
+                    }
+                    Ok(session.principal)
+                }
+            },
+        )
+}
+
+fn require_role(
+    auth: Arc<Auth>,
+    role: Role,
+) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    with_principal(auth, role).map(|_| ()).untuple_one()
+}
+
+/// Who made a request, for the config audit log
+fn with_actor(
+    auth: Arc<Auth>,
+    role: Role,
+) -> impl Filter<Extract = (String,), Error = warp::Rejection> + Clone {
+    with_principal(auth, role).map(|principal: Principal| principal.name)
+}
+
+/// Counts the request against the client's rate for `kind`.
+fn with_rate_limit(
+    limits: Arc<Limits>,
+    kind: LimitKind,
+) -> impl Filter<Extract = (), Error = warp::Rejection> + Clone {
+    warp::addr::remote()
+        .and_then(move |addr: Option<SocketAddr>| {
+            let limits = limits.clone();
+            async move {
+                match addr {
+                    Some(addr) => limits.check(kind, addr.ip()).map_err(|limited| {
+                        warp::reject::custom(ApiError::RateLimited {
+                            retry_after_secs: limited.retry_after_secs,
+                        })
+                    }),
+                    None => Ok(()),
+                }
+            }
+        })
+        .untuple_one()
+}
+
+fn with_limits(
+    limits: Arc<Limits>,
+) -> impl Filter<Extract = (Arc<Limits>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || limits.clone())
+}
+
+fn with_content_filter(
+    filter: Arc<ContentFilter>,
+) -> impl Filter<Extract = (Arc<ContentFilter>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || filter.clone())
+}
+
+fn with_preset_store(
+    presets: PresetStore,
+) -> impl Filter<Extract = (PresetStore,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || presets.clone())
+}
+
+fn with_play_log(
+    play_log: Arc<PlayLog>,
+) -> impl Filter<Extract = (Arc<PlayLog>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || play_log.clone())
+}
+
+fn with_morse_converter(
+    converter: Arc<MorseConverter>,
+) -> impl Filter<Extract = (Arc<MorseConverter>,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || converter.clone())
+}
+
+fn with_tempo_store(
+    tempo: TempoStore,
+) -> impl Filter<Extract = (TempoStore,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || tempo.clone())
+}
+
+fn with_config_store(
+    config: ConfigStore,
+) -> impl Filter<Extract = (ConfigStore,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || config.clone())
+}
+
+fn with_status_store(
+    status: StatusStore,
+) -> impl Filter<Extract = (StatusStore,), Error = std::convert::Infallible> + Clone {
+    warp::any().map(move || status.clone())
+}
+
+/// Lists matching messages in a stable order. With `limit` the list is
+/// paged; the `X-Next-Cursor` header of a page leads to the next one.
+async fn get_all_messages(
+    query: MessageQuery,
+    store: MessageStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    use warp::Reply;
+
+    if query
+        .limit
+        .is_some_and(|limit| !(1..=MAX_PAGE_SIZE).contains(&limit))
+    {
+        return Err(
+            ApiError::InvalidQuery(format!("limit must be between 1 and {MAX_PAGE_SIZE}")).into(),
+        );
+    }
+    let cursor = query
+        .cursor
+        .as_deref()
+        .map(Cursor::decode)
+        .transpose()
+        .map_err(ApiError::InvalidQuery)?;
+
+    let search = query.q.as_deref().map(Search::new);
+    let tags: Vec<String> = query
+        .tag
+        .as_deref()
+        .unwrap_or_default()
+        .split(',')
+        .map(normalize_tag)
+        .filter(|tag| !tag.is_empty())
+        .collect();
+    let now = Utc::now();
+    let matches = |message: &Message| {
+        let meta = &message.meta;
+        !message.in_trash()
+            && tags.iter().all(|tag| meta.has_tag(tag))
+            && query.author.as_deref().is_none_or(|author| {
+                meta.author
+                    .as_deref()
+                    .is_some_and(|a| a.to_lowercase() == author.trim().to_lowercase())
+            })
+            && query.language.as_deref().is_none_or(|language| {
+                meta.language
+                    .as_deref()
+                    .is_some_and(|l| l.eq_ignore_ascii_case(language.trim()))
+            })
+            && query
+                .min_priority
+                .is_none_or(|priority| meta.priority >= priority)
+            && query.status.is_none_or(|status| message.status == status)
+            && query
+                .state
+                .is_none_or(|state| message.lifecycle.state(now, message.send_count) == state)
+            && search.as_ref().is_none_or(|search| search.matches(message))
+    };
+    let messages = store.read();
+    let (page, next) = match message_query::paginate(
+        messages
+            .values()
+            .filter(|message| matches(message))
+            .collect(),
+        query.sort,
+        query.order,
+        cursor.as_ref(),
+        query.limit,
+    ) {
+        Ok(page) => page,
+        Err(e) => return Err(ApiError::InvalidQuery(e).into()),
+    };
+    let reply = warp::reply::json(&page);
+    Ok(match next {
+        Some(next) => {
+            warp::reply::with_header(reply, "x-next-cursor", next.encode()).into_response()
+        }
+        None => reply.into_response(),
+    })
+}
+
+async fn get_message(id: String, store: MessageStore) -> Result<impl warp::Reply, warp::Rejection> {
+    match store.read().get(&id).filter(|message| !message.in_trash()) {
+        Some(message) => Ok(warp::reply::json(message)),
+        None => Err(ApiError::NotFound(format!("Message {id} not found")).into()),
+    }
+}
+
+/// Shows how a text would play under the current config without storing
+/// it: the Morse code framed with the prosigns, every element with its
+/// timing, and a sample of the serial frames.
+async fn preview_message(
+    req: PreviewRequest,
+    morse_converter: Arc<MorseConverter>,
+    config_store: ConfigStore,
+    tempo_store: TempoStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    if req.tempo_ms == Some(0) {
+        return Err(ApiError::InvalidTempo.into());
+    }
+    let config = config_store.read().clone();
+    let alphabet = req.alphabet.filter(|a| !a.is_empty());
+    let conversion =
+        match morse_converter.convert(&req.text, alphabet.as_deref(), config.newline_policy) {
+            Ok(conversion) => conversion,
+            Err(e) => return Err(ApiError::Conversion(e).into()),
+        };
+    let played = frame_with_prosigns(
+        &conversion.morse_code,
+        config.start_prosign.as_deref(),
+        config.end_prosign.as_deref(),
+    );
+    let tempo_ms = req.tempo_ms.unwrap_or_else(|| *tempo_store.read());
+    let timeline = playback_timeline(&played, tempo_ms, &config);
+    let frame_count = timeline
+        .iter()
+        .filter(|entry| entry.element != MorseElement::LineBreak)
+        .count();
+    let frames: Vec<String> = timeline
+        .iter()
+        .filter_map(|entry| element_frame(entry.element, &config))
+        .take(
+            req.frame_limit
+                .unwrap_or(DEFAULT_PREVIEW_FRAMES)
+                .min(MAX_PREVIEW_FRAMES),
+        )
+        .map(|frame| frame.trim_end().to_string())
+        .collect();
+
+    let response = serde_json::json!({
+        "morse_code": conversion.morse_code,
+        "played_morse_code": played,
+        "unsupported_chars": conversion.unsupported,
+        "tempo_ms": tempo_ms,
+        "total_duration_ms": playback_duration_ms(&played, tempo_ms, &config),
+        "timeline": timeline,
+        "frame_count": frame_count,
+        "frames": frames,
+    });
+    Ok(warp::reply::json(&response))
+}
+
+async fn get_available_alphabets(
+    morse_converter: Arc<MorseConverter>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(warp::reply::json(&morse_converter.tables.list()))
+}
+
+async fn get_current_tempo(tempo_store: TempoStore) -> Result<impl warp::Reply, warp::Rejection> {
+    let tempo = *tempo_store.read();
+    let response = serde_json::json!({ "tempo_ms": tempo });
+    Ok(warp::reply::json(&response))
+}
+
+async fn save_messages_manually(
+    store: MessageStore,
+    repository: Repository,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let messages = store.read();
+    if let Err(e) = repository.replace_all(&messages) {
+        eprintln!("Failed to save messages: {}", e);
+        return Err(ApiError::SaveFailed(e.to_string()).into());
+    }
+    Ok(warp::reply::json(&serde_json::json!({
+        "success": true,
+        "message": "Messages saved successfully",
+        "count": messages.len()
+    })))
+}
+
+fn unknown_format_error(format: &str) -> ApiError {
+    ApiError::InvalidQuery(format!(
+        "Unknown format {format:?}: expected json, csv or text"
+    ))
+}
+
+/// Adds many messages at once, skipping ones already in the pool or repeated
+/// in the upload. Every item is reported; with `dry_run` nothing is stored.
+async fn import_messages(
+    query: ImportQuery,
+    content_type: Option<String>,
+    body: warp::hyper::body::Bytes,
+    runtime: ConfigRuntime,
+    limits: Arc<Limits>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let ConfigRuntime {
+        store,
+        repository,
+        morse_converter,
+        config_store,
+        ..
+    } = &runtime;
+    let format = match query.format.as_deref() {
+        None => TransferFormat::from_content_type(content_type.as_deref()),
+        Some(name) => match TransferFormat::from_name(name) {
+            Some(format) => format,
+            None => return Err(unknown_format_error(name).into()),
+        },
+    };
+    let Ok(body) = std::str::from_utf8(&body) else {
+        return Err(ApiError::InvalidImport("Import file is not valid UTF-8".to_string()).into());
+    };
+    let items = match message_io::parse_import(body, format) {
+        Ok(items) => items,
+        Err(e) => return Err(ApiError::InvalidImport(format!("Cannot read import: {e}")).into()),
+    };
+
+    let newline_policy = config_store.read().newline_policy;
+    let default_alphabet = query.alphabet.filter(|a| !a.is_empty());
+    let mut messages = store.write();
+    // Ids of the messages each key belongs to; None for items of this upload
+    // that a dry run does not store
+    let mut known: HashMap<(String, String), Option<String>> = messages
+        .values()
+        .filter(|m| !m.in_trash())
+        .map(|m| {
+            (
+                message_io::dedupe_key(&m.text, m.alphabet.as_deref()),
+                Some(m.id.clone()),
+            )
+        })
+        .collect();
+    let room = limits
+        .config
+        .max_messages
+        .saturating_sub(live_count(&messages));
+
+    let mut results = Vec::with_capacity(items.len());
+    let mut added = Vec::new();
+    for (index, item) in items.into_iter().enumerate() {
+        let text = item.text.trim().to_string();
+        let alphabet = item
+            .alphabet
+            .filter(|a| !a.is_empty())
+            .or_else(|| default_alphabet.clone());
+        let mut result = ImportItemResult {
+            index: index + 1,
+            text: text.clone(),
+            status: ImportStatus::Invalid,
+            id: None,
+            morse_code: None,
+            unsupported_chars: Vec::new(),
+            error: None,
+        };
+
+        let key = message_io::dedupe_key(&text, alphabet.as_deref());
+        if text.is_empty() {
+            result.error = Some("text is empty".to_string());
+        } else if let Some(id) = known.get(&key) {
+            result.status = ImportStatus::Duplicate;
+            result.id = id.clone();
+        } else if added.len() >= room {
+            result.error = Some(format!(
+                "message limit of {} reached",
+                limits.config.max_messages
+            ));
+        } else {
+            match morse_converter.convert(&text, alphabet.as_deref(), newline_policy) {
+                Err(e) => result.error = Some(e.to_string()),
+                Ok(conversion) if query.strict && !conversion.unsupported.is_empty() => {
+                    result.error = Some("text contains unsupported characters".to_string());
+                    result.unsupported_chars = conversion.unsupported;
+                }
+                Ok(conversion) => {
+                    let id = Uuid::new_v4().to_string();
+                    result.status = ImportStatus::New;
+                    result.morse_code = Some(conversion.morse_code.clone());
+                    result.unsupported_chars = conversion.unsupported.clone();
+                    if !query.dry_run {
+                        result.id = Some(id.clone());
+                    }
+                    known.insert(key, result.id.clone());
+                    added.push(Message {
+                        id,
+                        text,
+                        morse_code: conversion.morse_code,
+                        created_at: Utc::now(),
+                        last_sent: None,
+                        send_count: 0,
+                        unsupported_chars: conversion.unsupported,
+                        alphabet,
+                        encoded_with: conversion.encoded_with,
+                        status: MessageStatus::Approved,
+                        content_flags: Vec::new(),
+                        meta: MessageMeta::default(),
+                        lifecycle: Lifecycle::default(),
+                        deleted_at: None,
+                    });
+                }
+            }
+        }
+        results.push(result);
+    }
+
+    let count = |status| results.iter().filter(|r| r.status == status).count();
+    let report = ImportReport {
+        dry_run: query.dry_run,
+        total: results.len(),
+        added: added.len(),
+        duplicates: count(ImportStatus::Duplicate),
+        invalid: count(ImportStatus::Invalid),
+        items: results,
+    };
+
+    if !query.dry_run && !added.is_empty() {
+        for message in added {
+            messages.insert(message.id.clone(), message);
+        }
+        log_write_error(repository.replace_all(&messages));
+        println!("Imported {} messages", report.added);
+    }
+    Ok(warp::reply::json(&report))
+}
+
+/// All messages with their Morse code and send statistics, oldest first.
+async fn export_messages(
+    query: ExportQuery,
+    store: MessageStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let format = match query.format.as_deref() {
+        None => TransferFormat::Json,
+        Some(name) => match TransferFormat::from_name(name) {
+            Some(format) => format,
+            None => return Err(unknown_format_error(name).into()),
+        },
+    };
+    let mut messages: Vec<Message> = store
+        .read()
+        .values()
+        .filter(|m| !m.in_trash())
+        .cloned()
+        .collect();
+    messages.sort_by(|a, b| {
+        a.created_at
+            .cmp(&b.created_at)
+            .then_with(|| a.id.cmp(&b.id))
+    });
+
+    let (body, content_type, extension) = match format {
+        TransferFormat::Json => (
+            serde_json::to_string_pretty(&messages).unwrap_or_default(),
+            "application/json",
+            "json",
+        ),
+        TransferFormat::Csv => (
+            message_io::to_csv(&messages),
+            "text/csv; charset=utf-8",
+            "csv",
+        ),
+        TransferFormat::Text => (
+            message_io::to_text(&messages),
+            "text/plain; charset=utf-8",
+            "txt",
+        ),
+    };
+    Ok(warp::reply::with_header(
+        warp::reply::with_header(body, "content-type", content_type),
+        "content-disposition",
+        format!("attachment; filename=\"messages.{extension}\""),
+    ))
+}
+
+async fn reencode_all_messages(
+    store: MessageStore,
+    repository: Repository,
+    morse_converter: Arc<MorseConverter>,
+    config_store: ConfigStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let newline_policy = config_store.read().newline_policy;
+    let mut messages = store.write();
+    let changed = reencode_messages(&mut messages, &morse_converter, newline_policy, false);
+    log_write_error(repository.replace_all(&messages));
+    println!(
+        "Re-encoded {} messages, {} changed",
+        messages.len(),
+        changed.len()
+    );
+
+    let response = serde_json::json!({
+        "success": true,
+        "total": messages.len(),
+        "changed_count": changed.len(),
+        "changed": changed,
+    });
+    Ok(warp::reply::json(&response))
+}
+
+async fn get_storage_status(
+    status_store: StatusStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let status = status_store.read().clone();
+    Ok(warp::reply::json(&status))
+}
+
+fn config_reply(runtime: &ConfigRuntime, preset_store: &PresetStore) -> warp::reply::Json {
+    let config = runtime.config_store.read().clone();
+    warp::reply::json(&ConfigResponse {
+        config: &config,
+        active_preset: preset_store.read().active_preset.clone(),
+        pending_config: runtime.pending.read().clone(),
+    })
+}
+
+async fn get_transformer_config(
+    runtime: ConfigRuntime,
+    preset_store: PresetStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(config_reply(&runtime, &preset_store))
+}
+
+/// Makes `new_config` the running config: persists it and re-encodes messages
+/// when the newline policy changed. With `interrupt` the current message stops.
+fn apply_config(new_config: &TransformerConfig, runtime: &ConfigRuntime, interrupt: bool) {
+    let old_policy = runtime.config_store.read().newline_policy;
+    *runtime.config_store.write() = new_config.clone();
+    save_config_to_file(new_config, &DATA_PATHS.config_file);
+
+    if new_config.newline_policy != old_policy {
+        let mut messages = runtime.store.write();
+        let changed = reencode_messages(
+            &mut messages,
+            &runtime.morse_converter,
+            new_config.newline_policy,
+            true,
+        );
+        println!(
+            "Newline policy changed to {:?} - re-encoded {} messages",
+            new_config.newline_policy,
+            changed.len()
+        );
+        log_write_error(runtime.repository.replace_all(&messages));
+    }
+
+    if interrupt {
+        CONFIG_CHANGED.store(true, Ordering::SeqCst);
+        println!("Config updated - current message sending will be interrupted");
+    } else {
+        println!("Config updated at message boundary");
+    }
+}
+
+/// Records a config change in the audit log and applies it now, or holds it
+/// until the current message finishes.
+fn change_config(
+    new_config: TransformerConfig,
+    actor: &str,
+    source: &str,
+    apply: ApplyMode,
+    runtime: &ConfigRuntime,
+) {
+    // Compare against the newest config, which may still be waiting
+    let previous = runtime
+        .pending
+        .read()
+        .clone()
+        .unwrap_or_else(|| runtime.config_store.read().clone());
+    match runtime
+        .audit_log
+        .record(actor, source, apply, &previous, &new_config)
+    {
+        Ok(Some(entry)) => println!(
+            "Config version {} by {} ({}): {} fields changed",
+            entry.version,
+            actor,
+            source,
+            entry.changes.len()
+        ),
+        Ok(None) => {}
+        Err(e) => eprintln!("Failed to write config audit log: {}", e),
+    }
+
+    match apply {
+        ApplyMode::Immediate => {
+            *runtime.pending.write() = None;
+            apply_config(&new_config, runtime, true);
+        }
+        ApplyMode::NextMessage => {
+            println!("Config change will apply after the current message");
+            *runtime.pending.write() = Some(new_config);
+        }
+    }
+}
+
+/// Applies a config held by `ApplyMode::NextMessage`, making a preset it came
+/// from the active one. Called by the message scheduler between messages.
+fn apply_pending_config(runtime: &ConfigRuntime, preset_store: &PresetStore) {
+    let pending = runtime.pending.write().take();
+    if let Some(config) = pending {
+        apply_config(&config, runtime, false);
+        let mut presets = preset_store.write();
+        if presets.activate_pending(&config) {
+            presets::save_presets(&presets, &DATA_PATHS.presets_file);
+        }
+    }
+}
+
+/// Shared by PUT, PATCH and revert once the new config is known to be valid
+fn finish_config_change(
+    new_config: TransformerConfig,
+    actor: &str,
+    source: &str,
+    apply: ApplyMode,
+    runtime: &ConfigRuntime,
+    preset_store: &PresetStore,
+) -> warp::reply::Json {
+    let mut presets = preset_store.write();
+    if presets.note_config_change(&new_config) {
+        presets::save_presets(&presets, &DATA_PATHS.presets_file);
+    }
+    drop(presets);
+
+    change_config(new_config, actor, source, apply, runtime);
+    config_reply(runtime, preset_store)
+}
+
+async fn update_transformer_config(
+    query: ApplyQuery,
+    actor: String,
+    body: serde_json::Value,
+    runtime: ConfigRuntime,
+    preset_store: PresetStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let new_config = match TransformerConfig::from_json(body) {
+        Ok(config) => config,
+        Err(errors) => return Err(ApiError::InvalidConfig(errors).into()),
+    };
+    Ok(finish_config_change(
+        new_config,
+        &actor,
+        "put",
+        query.apply,
+        &runtime,
+        &preset_store,
+    ))
+}
+
+/// Applies an RFC 7386 JSON merge patch: objects merge recursively, `null`
+/// removes a field (back to its default) and anything else replaces it.
+fn merge_patch(target: &mut serde_json::Value, patch: &serde_json::Value) {
+    let serde_json::Value::Object(patch) = patch else {
+        *target = patch.clone();
+        return;
+    };
+    if !target.is_object() {
+        *target = serde_json::Value::Object(serde_json::Map::new());
+    }
+    if let serde_json::Value::Object(target) = target {
+        for (key, value) in patch {
+            if value.is_null() {
+                target.remove(key);
+            } else {
+                merge_patch(
+                    target.entry(key.clone()).or_insert(serde_json::Value::Null),
+                    value,
+                );
+            }
+        }
+    }
+}
+
+async fn patch_transformer_config(
+    query: ApplyQuery,
+    actor: String,
+    body: warp::hyper::body::Bytes,
+    runtime: ConfigRuntime,
+    preset_store: PresetStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let patch: serde_json::Value =
+        serde_json::from_slice(&body).map_err(|e| ApiError::InvalidJson(e.to_string()))?;
+    // Patch the newest config, including one still waiting for the next message
+    let base = runtime
+        .pending
+        .read()
+        .clone()
+        .unwrap_or_else(|| runtime.config_store.read().clone());
+    let mut merged = serde_json::to_value(&base).unwrap_or_default();
+    merge_patch(&mut merged, &patch);
+
+    let new_config = match TransformerConfig::from_json(merged) {
+        Ok(config) => config,
+        Err(errors) => return Err(ApiError::InvalidConfig(errors).into()),
+    };
+    Ok(finish_config_change(
+        new_config,
+        &actor,
+        "patch",
+        query.apply,
+        &runtime,
+        &preset_store,
+    ))
+}
+
+async fn get_config_audit_log(runtime: ConfigRuntime) -> Result<impl warp::Reply, warp::Rejection> {
+    match runtime.audit_log.list() {
+        Ok(entries) => Ok(warp::reply::json(&entries)),
+        Err(e) => {
+            eprintln!("Failed to read config audit log: {}", e);
+            Err(ApiError::AuditUnavailable(e.to_string()).into())
+        }
+    }
+}
+
+async fn revert_config_version(
+    version: u64,
+    query: ApplyQuery,
+    actor: String,
+    runtime: ConfigRuntime,
+    preset_store: PresetStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let entry = match runtime.audit_log.get(version) {
+        Ok(Some(entry)) => entry,
+        Ok(None) => return Err(ApiError::VersionNotFound(version).into()),
+        Err(e) => return Err(ApiError::AuditUnavailable(e.to_string()).into()),
+    };
+    // Versions written before validation existed may not pass it
+    if let Err(errors) = entry.config.validate() {
+        return Err(ApiError::InvalidConfig(errors).into());
+    }
+
+    Ok(finish_config_change(
+        entry.config,
+        &actor,
+        &format!("revert:{version}"),
+        query.apply,
+        &runtime,
+        &preset_store,
+    ))
+}
+
+/// Preset names arrive percent-encoded in the path
+fn decode_path_param(value: &str) -> String {
+    percent_encoding::percent_decode_str(value)
+        .decode_utf8_lossy()
+        .into_owned()
+}
+
+async fn list_config_presets(
+    preset_store: PresetStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let presets = preset_store.read();
+    let list: Vec<_> = presets.presets.values().collect();
+    Ok(warp::reply::json(&list))
+}
+
+async fn create_config_preset(
+    req: CreatePresetRequest,
+    preset_store: PresetStore,
+    config_store: ConfigStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let config = match req.config.map(TransformerConfig::from_json) {
+        Some(Ok(config)) => config,
+        Some(Err(errors)) => return Err(ApiError::InvalidConfig(errors).into()),
+        None => config_store.read().clone(),
+    };
+    let mut presets = preset_store.write();
+    let reply = match presets.create(&req.name, config, req.overwrite) {
+        Ok(preset) => {
+            println!("Saved config preset '{}'", preset.name);
+            warp::reply::with_status(warp::reply::json(preset), warp::http::StatusCode::CREATED)
+        }
+        Err(e) => return Err(ApiError::Preset(e).into()),
+    };
+    presets::save_presets(&presets, &DATA_PATHS.presets_file);
+    Ok(reply)
+}
 
-    let save_messages = api
-        .and(warp::path("messages"))
-        .and(warp::path("save"))
-        .and(warp::path::end())
-        .and(warp::post())
-        .and(with_store(messages_store.clone()))
-        .and_then(save_messages_manually);
+/// Switches the running config to the named preset. Shared by the apply
+/// endpoint and the preset scheduler.
+fn apply_preset_by_name(
+    name: &str,
+    actor: &str,
+    apply: ApplyMode,
+    preset_store: &PresetStore,
+    runtime: &ConfigRuntime,
+) -> Result<(), PresetError> {
+    let config = preset_store.read().get(name)?.config.clone();
+    config.validate().map_err(PresetError::InvalidConfig)?;
+    change_config(config, actor, &format!("preset:{name}"), apply, runtime);
+
+    let mut presets = preset_store.write();
+    match apply {
+        ApplyMode::Immediate => {
+            presets.pending_preset = None;
+            presets.active_preset = Some(name.to_string());
+            presets::save_presets(&presets, &DATA_PATHS.presets_file);
+            println!("Applied config preset '{}'", name);
+        }
+        // Becomes active in `apply_pending_config`
+        ApplyMode::NextMessage => {
+            presets.pending_preset = Some(name.to_string());
+            println!(
+                "Config preset '{}' will apply after the current message",
+                name
+            );
+        }
+    }
+    Ok(())
+}
 
-    let get_config = api
-        .and(warp::path("config"))
-        .and(warp::path::end())
-        .and(warp::get())
-        .and(with_config_store(config_store.clone()))
-        .and_then(get_transformer_config);
+async fn apply_config_preset(
+    name: String,
+    query: ApplyQuery,
+    actor: String,
+    preset_store: PresetStore,
+    runtime: ConfigRuntime,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let name = decode_path_param(&name);
+    match apply_preset_by_name(&name, &actor, query.apply, &preset_store, &runtime) {
+        Ok(()) => Ok(config_reply(&runtime, &preset_store)),
+        Err(e) => Err(ApiError::Preset(e).into()),
+    }
+}
 
-    let update_config = api
-        .and(warp::path("config"))
-        .and(warp::path::end())
-        .and(warp::put())
-        .and(warp::body::json())
-        .and(with_config_store(config_store.clone()))
-        .and_then(update_transformer_config);
+async fn duplicate_config_preset(
+    name: String,
+    req: DuplicatePresetRequest,
+    preset_store: PresetStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let name = decode_path_param(&name);
+    let mut presets = preset_store.write();
+    let reply = match presets.duplicate(&name, &req.name) {
+        Ok(preset) => {
+            warp::reply::with_status(warp::reply::json(preset), warp::http::StatusCode::CREATED)
+        }
+        Err(e) => return Err(ApiError::Preset(e).into()),
+    };
+    presets::save_presets(&presets, &DATA_PATHS.presets_file);
+    Ok(reply)
+}
 
-    let routes = index
-        .or(static_files)
-        .or(get_messages)
-        .or(create_message)
-        .or(update_message)
-        .or(delete_message)
-        .or(get_tempo)
-        .or(save_messages)
-        .or(get_config)
-        .or(update_config)
-        .with(cors);
+async fn delete_config_preset(
+    name: String,
+    preset_store: PresetStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let name = decode_path_param(&name);
+    let mut presets = preset_store.write();
+    if let Err(e) = presets.delete(&name) {
+        return Err(ApiError::Preset(e).into());
+    }
+    presets::save_presets(&presets, &DATA_PATHS.presets_file);
+    Ok(warp::reply::with_status(
+        "",
+        warp::http::StatusCode::NO_CONTENT,
+    ))
+}
 
-    let shutdown_store = message_store.clone();
-    let shutdown_config = config_store.clone();
-    ctrlc::set_handler(move || {
-        println!("Received Ctrl+C, saving messages and config before shutdown...");
-        let messages = shutdown_store.read();
-        save_messages_to_file(&messages, MESSAGES_FILE_PATH);
-        let config = shutdown_config.read();
-        save_config_to_file(&config, CONFIG_FILE_PATH);
-        std::process::exit(0);
-    })
-    .expect("Error setting Ctrl-C handler");
+async fn get_preset_schedule(
+    preset_store: PresetStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    Ok(warp::reply::json(&preset_store.read().schedule))
+}
 
-    println!("Morse Code Web API running on http://localhost:3030");
-    warp::serve(routes).run(([0, 0, 0, 0], 3030)).await;
+async fn create_schedule_entry(
+    req: CreateScheduleRequest,
+    preset_store: PresetStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut presets = preset_store.write();
+    let reply = match presets.add_schedule(
+        Uuid::new_v4().to_string(),
+        &req.preset,
+        req.daily_at.as_deref(),
+        req.at,
+    ) {
+        Ok(entry) => {
+            warp::reply::with_status(warp::reply::json(entry), warp::http::StatusCode::CREATED)
+        }
+        Err(e) => return Err(ApiError::Preset(e).into()),
+    };
+    presets::save_presets(&presets, &DATA_PATHS.presets_file);
+    Ok(reply)
 }
 
-fn with_store(
-    store: MessageStore,
-) -> impl Filter<Extract = (MessageStore,), Error = std::convert::Infallible> + Clone {
-    warp::any().map(move || store.clone())
+async fn delete_schedule_entry(
+    id: String,
+    preset_store: PresetStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut presets = preset_store.write();
+    if let Err(e) = presets.remove_schedule(&id) {
+        return Err(ApiError::Preset(e).into());
+    }
+    presets::save_presets(&presets, &DATA_PATHS.presets_file);
+    Ok(warp::reply::with_status(
+        "",
+        warp::http::StatusCode::NO_CONTENT,
+    ))
 }
 
-fn with_morse_converter(
-    converter: Arc<MorseConverter>,
-) -> impl Filter<Extract = (Arc<MorseConverter>,), Error = std::convert::Infallible> + Clone {
-    warp::any().map(move || converter.clone())
+/// Parses a history filter bound. Plain dates start at midnight UTC; with
+/// `end_of_day` they refer to the following midnight so the day is included.
+fn parse_history_bound(value: &str, end_of_day: bool) -> Result<DateTime<Utc>, String> {
+    if let Ok(time) = DateTime::parse_from_rfc3339(value) {
+        return Ok(time.with_timezone(&Utc));
+    }
+    let date = NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| format!("Invalid date {value:?}: expected YYYY-MM-DD or RFC 3339"))?;
+    let date = if end_of_day {
+        date.succ_opt().unwrap_or(date)
+    } else {
+        date
+    };
+    Ok(date.and_hms_opt(0, 0, 0).unwrap_or_default().and_utc())
 }
 
-fn with_tempo_store(
-    tempo: TempoStore,
-) -> impl Filter<Extract = (TempoStore,), Error = std::convert::Infallible> + Clone {
-    warp::any().map(move || tempo.clone())
+async fn get_play_history(
+    query: HistoryQuery,
+    play_log: Arc<PlayLog>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    use warp::Reply;
+
+    let from = query
+        .from
+        .as_deref()
+        .map(|v| parse_history_bound(v, false))
+        .transpose()
+        .map_err(ApiError::InvalidQuery)?;
+    let until = query
+        .to
+        .as_deref()
+        .map(|v| parse_history_bound(v, true))
+        .transpose()
+        .map_err(ApiError::InvalidQuery)?;
+
+    let records = play_log.query(from, until).map_err(|e| {
+        eprintln!("Failed to read play history: {}", e);
+        ApiError::HistoryUnavailable(e.to_string())
+    })?;
+
+    match query.format.as_deref() {
+        None | Some("json") => Ok(warp::reply::json(&records).into_response()),
+        Some("csv") => Ok(warp::reply::with_header(
+            warp::reply::with_header(
+                play_log::to_csv(&records),
+                "content-type",
+                "text/csv; charset=utf-8",
+            ),
+            "content-disposition",
+            "attachment; filename=\"play_history.csv\"",
+        )
+        .into_response()),
+        Some(other) => Err(ApiError::InvalidQuery(format!(
+            "Unknown format {other:?}: expected json or csv"
+        ))
+        .into()),
+    }
 }
 
-fn with_config_store(
-    config: ConfigStore,
-) -> impl Filter<Extract = (ConfigStore,), Error = std::convert::Infallible> + Clone {
-    warp::any().map(move || config.clone())
+/// Reads a password from stdin and prints its hash for `auth.toml`.
+fn print_password_hash() {
+    let mut password = String::new();
+    if let Err(e) = std::io::stdin().read_line(&mut password) {
+        eprintln!("Failed to read password: {}", e);
+        std::process::exit(1);
+    }
+    let password = password.trim_end_matches(['\r', '\n']);
+    if password.is_empty() {
+        eprintln!("Password must not be empty");
+        std::process::exit(2);
+    }
+    match auth::hash_password(password) {
+        Ok(hash) => println!("{}", hash),
+        Err(e) => {
+            eprintln!("Failed to hash password: {}", e);
+            std::process::exit(1);
+        }
+    }
 }
 
-async fn get_all_messages(store: MessageStore) -> Result<impl warp::Reply, warp::Rejection> {
-    let messages: Vec<Message> = store.read().values().cloned().collect();
-    Ok(warp::reply::json(&messages))
+/// CORS for the origins listed in `auth.toml`, or `None` when only the
+/// service's own pages may call the API
+fn cors_for(origins: &[String]) -> Option<warp::cors::Builder> {
+    let cors = warp::cors()
+        .allow_headers(vec!["content-type", "authorization", "x-operator"])
+        .allow_methods(vec!["GET", "POST", "PUT", "PATCH", "DELETE"])
+        .expose_headers(vec!["x-next-cursor"]);
+    if origins.iter().any(|origin| origin == "*") {
+        return Some(cors.allow_any_origin());
+    }
+    // warp panics on malformed origins, so only pass scheme://host[:port]
+    let valid: Vec<&str> = origins
+        .iter()
+        .map(|origin| origin.trim_end_matches('/'))
+        .filter(|origin| {
+            let valid = origin.split_once("://").is_some_and(|(scheme, host)| {
+                matches!(scheme, "http" | "https") && !host.is_empty() && !host.contains('/')
+            });
+            if !valid {
+                eprintln!("Ignoring invalid CORS origin {:?}", origin);
+            }
+            valid
+        })
+        .collect();
+    (!valid.is_empty()).then(|| cors.allow_origins(valid))
 }
 
-async fn get_current_tempo(tempo_store: TempoStore) -> Result<impl warp::Reply, warp::Rejection> {
-    let tempo = *tempo_store.read();
-    let response = serde_json::json!({ "tempo_ms": tempo });
-    Ok(warp::reply::json(&response))
+fn session_cookie(token: &str, max_age_secs: i64) -> String {
+    format!("{SESSION_COOKIE}={token}; Path=/; HttpOnly; SameSite=Strict; Max-Age={max_age_secs}")
 }
 
-async fn save_messages_manually(store: MessageStore) -> Result<impl warp::Reply, warp::Rejection> {
-    let messages = store.read();
-    save_messages_to_file(&messages, MESSAGES_FILE_PATH);
+async fn log_in(req: LoginRequest, auth: Arc<Auth>) -> Result<impl warp::Reply, warp::Rejection> {
+    if !auth.enabled() {
+        return Err(ApiError::AuthDisabled.into());
+    }
+    let Some((token, session)) = auth.login(req.name.trim(), &req.password) else {
+        println!("Failed login for {:?}", req.name);
+        return Err(ApiError::InvalidCredentials.into());
+    };
 
+    println!(
+        "{} logged in as {}",
+        session.principal.name, session.principal.role
+    );
+    let cookie = session_cookie(&token, auth.session_hours() * 3600);
     let response = serde_json::json!({
-        "success": true,
-        "message": "Messages saved successfully",
-        "count": messages.len()
+        "token": token,
+        "name": session.principal.name,
+        "role": session.principal.role,
+        "expires_at": session.expires_at,
     });
-    Ok(warp::reply::json(&response))
+    Ok(warp::reply::with_header(
+        warp::reply::json(&response),
+        "set-cookie",
+        cookie,
+    ))
 }
 
-async fn get_transformer_config(
-    config_store: ConfigStore,
+async fn log_out(
+    cookie: Option<String>,
+    authorization: Option<String>,
+    auth: Arc<Auth>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    let config = config_store.read().clone();
-    Ok(warp::reply::json(&config))
+    if let Some(token) = request_token(cookie, authorization) {
+        auth.logout(&token);
+    }
+    Ok(warp::reply::with_header(
+        warp::reply::json(&serde_json::json!({ "success": true })),
+        "set-cookie",
+        session_cookie("", 0),
+    ))
 }
 
-async fn update_transformer_config(
-    new_config: TransformerConfig,
-    config_store: ConfigStore,
+async fn get_current_user(
+    principal: Principal,
+    auth: Arc<Auth>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    *config_store.write() = new_config.clone();
-    save_config_to_file(&new_config, CONFIG_FILE_PATH);
+    Ok(warp::reply::json(&serde_json::json!({
+        "auth_enabled": auth.enabled(),
+        "name": principal.name,
+        "role": principal.role,
+    })))
+}
+
+async fn skip_current_message() -> Result<impl warp::Reply, warp::Rejection> {
+    SKIP_REQUESTED.store(true, Ordering::SeqCst);
+    println!("Skip requested - current message will be interrupted");
+    Ok(warp::reply::json(&serde_json::json!({ "success": true })))
+}
+
+/// Applies metadata and lifecycle changes, each all-or-nothing, and returns
+/// the problems of both.
+fn apply_field_changes(
+    meta: &mut MessageMeta,
+    meta_changes: MetaChanges,
+    lifecycle: &mut Lifecycle,
+    lifecycle_changes: LifecycleChanges,
+) -> Vec<FieldError> {
+    let mut errors = meta.apply(meta_changes).err().unwrap_or_default();
+    errors.extend(lifecycle.apply(lifecycle_changes).err().unwrap_or_default());
+    errors
+}
 
-    CONFIG_CHANGED.store(true, Ordering::SeqCst);
-    println!("Config updated - current message sending will be interrupted");
+/// Encodes the text of a new message. Fails when the alphabet is unknown or,
+/// with `strict`, the text has unsupported characters.
+fn new_message(
+    text: String,
+    alphabet: Option<String>,
+    strict: bool,
+    status: MessageStatus,
+    morse_converter: &MorseConverter,
+    config_store: &ConfigStore,
+) -> Result<Message, ApiError> {
+    let newline_policy = config_store.read().newline_policy;
+    let alphabet = alphabet.filter(|a| !a.is_empty());
+    let conversion = morse_converter
+        .convert(&text, alphabet.as_deref(), newline_policy)
+        .map_err(ApiError::Conversion)?;
+    if strict && !conversion.unsupported.is_empty() {
+        return Err(ApiError::UnsupportedCharacters(conversion.unsupported));
+    }
 
-    Ok(warp::reply::json(&new_config))
+    Ok(Message {
+        id: Uuid::new_v4().to_string(),
+        text,
+        morse_code: conversion.morse_code,
+        created_at: Utc::now(),
+        last_sent: None,
+        send_count: 0,
+        unsupported_chars: conversion.unsupported,
+        alphabet,
+        encoded_with: conversion.encoded_with,
+        status,
+        content_flags: Vec::new(),
+        meta: MessageMeta::default(),
+        lifecycle: Lifecycle::default(),
+        deleted_at: None,
+    })
 }
 
 async fn create_new_message(
     req: CreateMessageRequest,
     store: MessageStore,
+    repository: Repository,
     morse_converter: Arc<MorseConverter>,
+    config_store: ConfigStore,
+    limits: Arc<Limits>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
-    let id = Uuid::new_v4().to_string();
-    let morse_code = morse_converter.morse_converter(&req.text);
+    if live_count(&store.read()) >= limits.config.max_messages {
+        return Err(ApiError::MessageLimitReached(limits.config.max_messages).into());
+    }
+    let mut meta = MessageMeta::default();
+    let mut lifecycle = Lifecycle::default();
+    let errors = apply_field_changes(&mut meta, req.meta, &mut lifecycle, req.lifecycle);
+    if !errors.is_empty() {
+        return Err(ApiError::InvalidMetadata(errors).into());
+    }
+    let mut message = new_message(
+        req.text,
+        req.alphabet,
+        req.strict,
+        MessageStatus::Approved,
+        &morse_converter,
+        &config_store,
+    )?;
+    message.meta = meta;
+    message.lifecycle = lifecycle;
+
+    log_write_error(repository.upsert(&message));
+    store.write().insert(message.id.clone(), message.clone());
+    Ok(warp::reply::json(&message))
+}
 
-    let message = Message {
-        id: id.clone(),
-        text: req.text,
+/// Runs the content filter over a message as it would be played: framed
+/// with the prosigns, at the slowest tempo.
+fn check_content(
+    filter: &ContentFilter,
+    text: &str,
+    morse_code: &str,
+    config_store: &ConfigStore,
+) -> Vec<ContentFlag> {
+    let config = config_store.read();
+    let framed = frame_with_prosigns(
         morse_code,
-        created_at: Utc::now(),
-        last_sent: None,
-        send_count: 0,
-    };
+        config.start_prosign.as_deref(),
+        config.end_prosign.as_deref(),
+    );
+    let duration_ms = playback_duration_ms(&framed, slowest_tempo_ms(&config), &config);
+    filter.check(text, duration_ms)
+}
 
-    store.write().insert(id, message.clone());
-    Ok(warp::reply::json(&message))
+/// Stores a visitor's message. It waits for moderation unless the filter
+/// passes it and auto-approval is on. The reply holds only the id and that
+/// status, so the endpoint reveals nothing about the pool or why the filter
+/// held a message back.
+async fn submit_message(
+    req: SubmitMessageRequest,
+    store: MessageStore,
+    repository: Repository,
+    morse_converter: Arc<MorseConverter>,
+    config_store: ConfigStore,
+    content_filter: Arc<ContentFilter>,
+    limits: Arc<Limits>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    {
+        let messages = store.read();
+        if live_count(&messages) >= limits.config.max_messages {
+            return Err(ApiError::MessageLimitReached(limits.config.max_messages).into());
+        }
+        let pending = messages
+            .values()
+            .filter(|m| m.status == MessageStatus::Pending && !m.in_trash())
+            .count();
+        if pending >= limits.config.max_pending {
+            return Err(ApiError::ModerationQueueFull.into());
+        }
+    }
+    if req.text.trim().is_empty() {
+        return Err(ApiError::EmptyText.into());
+    }
+    // Characters that cannot be encoded are left to the moderator unless
+    // nothing playable remains
+    let mut message = new_message(
+        req.text.trim().to_string(),
+        req.alphabet,
+        false,
+        MessageStatus::Pending,
+        &morse_converter,
+        &config_store,
+    )?;
+    if message.morse_code.trim().is_empty() {
+        return Err(ApiError::EmptyMorse.into());
+    }
+
+    message.content_flags = check_content(
+        &content_filter,
+        &message.text,
+        &message.morse_code,
+        &config_store,
+    );
+    if message.content_flags.is_empty() && content_filter.auto_approve() {
+        message.status = MessageStatus::Approved;
+        println!("New submission {} passed the filter", message.id);
+    } else {
+        let reasons: Vec<String> = message
+            .content_flags
+            .iter()
+            .map(ContentFlag::to_string)
+            .collect();
+        println!(
+            "New submission {} waiting for moderation{}",
+            message.id,
+            if reasons.is_empty() {
+                String::new()
+            } else {
+                format!(": {}", reasons.join("; "))
+            }
+        );
+    }
+
+    log_write_error(repository.upsert(&message));
+    let response = serde_json::json!({
+        "id": message.id,
+        "status": message.status,
+    });
+    store.write().insert(message.id.clone(), message);
+    Ok(warp::reply::with_status(
+        warp::reply::json(&response),
+        warp::http::StatusCode::ACCEPTED,
+    ))
+}
+
+/// Lists messages awaiting moderation, oldest first, or those with another
+/// status given by `?status=`.
+async fn get_moderation_queue(
+    query: ModerationQuery,
+    store: MessageStore,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let status = query.status.unwrap_or(MessageStatus::Pending);
+    let mut messages: Vec<Message> = store
+        .read()
+        .values()
+        .filter(|message| message.status == status && !message.in_trash())
+        .cloned()
+        .collect();
+    messages.sort_by_key(|message| message.created_at);
+    Ok(warp::reply::json(&messages))
+}
+
+/// Approves or rejects a message. Either decision can be changed later;
+/// rejecting an approved message takes it out of the play pool.
+async fn moderate_message(
+    id: String,
+    status: MessageStatus,
+    actor: String,
+    store: MessageStore,
+    repository: Repository,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut messages = store.write();
+    let Some(message) = messages.get_mut(&id).filter(|m| !m.in_trash()) else {
+        return Err(ApiError::NotFound(format!("Message {id} not found")).into());
+    };
+    message.status = status;
+    log_write_error(repository.upsert(message));
+    println!("Message {id} marked {status:?} by {actor}");
+    Ok(warp::reply::json(message))
 }
 
 async fn update_existing_message(
     id: String,
     req: UpdateMessageRequest,
     store: MessageStore,
+    repository: Repository,
     morse_converter: Arc<MorseConverter>,
+    config_store: ConfigStore,
+    content_filter: Arc<ContentFilter>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
+    let newline_policy = config_store.read().newline_policy;
     let mut messages = store.write();
 
-    if let Some(message) = messages.get_mut(&id) {
-        message.text = req.text.clone();
-        let normalized_text = req.text.replace('\n', " ").replace('\r', "");
-        message.morse_code = morse_converter.morse_converter(&normalized_text);
+    if let Some(message) = messages.get_mut(&id).filter(|m| !m.in_trash()) {
+        let mut meta = message.meta.clone();
+        let mut lifecycle = message.lifecycle.clone();
+        let errors = apply_field_changes(&mut meta, req.meta, &mut lifecycle, req.lifecycle);
+        if !errors.is_empty() {
+            return Err(ApiError::InvalidMetadata(errors).into());
+        }
+        let text = req.text.unwrap_or_else(|| message.text.clone());
+        let alphabet = match req.alphabet {
+            Some(alphabet) => Some(alphabet).filter(|a| !a.is_empty()),
+            None => message.alphabet.clone(),
+        };
+        let conversion = match morse_converter.convert(&text, alphabet.as_deref(), newline_policy) {
+            Ok(conversion) => conversion,
+            Err(e) => return Err(ApiError::Conversion(e).into()),
+        };
+        if req.strict && !conversion.unsupported.is_empty() {
+            return Err(ApiError::UnsupportedCharacters(conversion.unsupported).into());
+        }
+
+        message.text = text;
+        message.morse_code = conversion.morse_code;
+        message.unsupported_chars = conversion.unsupported;
+        message.alphabet = alphabet;
+        message.encoded_with = conversion.encoded_with;
+        message.meta = meta;
+        message.lifecycle = lifecycle;
+        // A moderator's edit of a submission is checked again
+        if message.status == MessageStatus::Pending {
+            message.content_flags = check_content(
+                &content_filter,
+                &message.text,
+                &message.morse_code,
+                &config_store,
+            );
+        }
+        log_write_error(repository.upsert(message));
         Ok(warp::reply::json(message))
     } else {
-        Err(warp::reject::not_found())
+        Err(ApiError::NotFound(format!("Message {id} not found")).into())
     }
 }
 
+/// Moves a message to the trash, from where it can be restored until the
+/// retention period ends.
 async fn delete_existing_message(
     id: String,
     store: MessageStore,
+    repository: Repository,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     let mut messages = store.write();
 
-    if messages.remove(&id).is_some() {
+    if let Some(message) = messages.get_mut(&id).filter(|m| !m.in_trash()) {
+        message.deleted_at = Some(Utc::now());
+        log_write_error(repository.upsert(message));
         Ok(warp::reply::with_status(
             "",
             warp::http::StatusCode::NO_CONTENT,
         ))
     } else {
-        Err(warp::reject::not_found())
+        Err(ApiError::NotFound(format!("Message {id} not found")).into())
     }
 }
 
-fn start_message_scheduler(
+/// Lists the trash, most recently deleted first.
+async fn get_trash(store: MessageStore) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut messages: Vec<Message> = store
+        .read()
+        .values()
+        .filter(|message| message.in_trash())
+        .cloned()
+        .collect();
+    messages.sort_by_key(|message| std::cmp::Reverse(message.deleted_at));
+    Ok(warp::reply::json(&messages))
+}
+
+async fn restore_message(
+    id: String,
     store: MessageStore,
-    morse_converter: Arc<MorseConverter>,
+    repository: Repository,
+    limits: Arc<Limits>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut messages = store.write();
+    if !messages.get(&id).is_some_and(Message::in_trash) {
+        return Err(ApiError::NotFound(format!("Message {id} is not in the trash")).into());
+    }
+    if live_count(&messages) >= limits.config.max_messages {
+        return Err(ApiError::MessageLimitReached(limits.config.max_messages).into());
+    }
+    let Some(message) = messages.get_mut(&id) else {
+        return Err(ApiError::NotFound(format!("Message {id} is not in the trash")).into());
+    };
+    message.deleted_at = None;
+    log_write_error(repository.upsert(message));
+    Ok(warp::reply::json(message))
+}
+
+/// Deletes a message in the trash for good.
+async fn purge_message(
+    id: String,
+    store: MessageStore,
+    repository: Repository,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let mut messages = store.write();
+    if !messages.get(&id).is_some_and(Message::in_trash) {
+        return Err(ApiError::NotFound(format!("Message {id} is not in the trash")).into());
+    }
+    messages.remove(&id);
+    log_write_error(repository.delete(&id));
+    Ok(warp::reply::with_status(
+        "",
+        warp::http::StatusCode::NO_CONTENT,
+    ))
+}
+
+async fn empty_trash(
+    store: MessageStore,
+    repository: Repository,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let purged = purge_trash(&store, &repository, |_| true);
+    Ok(warp::reply::json(&serde_json::json!({ "purged": purged })))
+}
+
+/// Deletes messages that have been in the trash longer than the retention
+/// period.
+fn purge_expired_trash(store: &MessageStore, repository: &Repository, retention_days: u32) {
+    let cutoff = Utc::now() - chrono::Duration::days(i64::from(retention_days));
+    let purged = purge_trash(store, repository, |deleted_at| deleted_at < cutoff);
+    if purged > 0 {
+        println!("Purged {purged} messages kept in the trash for over {retention_days} days");
+    }
+}
+
+fn purge_trash(
+    store: &MessageStore,
+    repository: &Repository,
+    due: impl Fn(DateTime<Utc>) -> bool,
+) -> usize {
+    let mut messages = store.write();
+    let ids: Vec<String> = messages
+        .values()
+        .filter(|m| m.deleted_at.is_some_and(&due))
+        .map(|m| m.id.clone())
+        .collect();
+    for id in &ids {
+        messages.remove(id);
+        log_write_error(repository.delete(id));
+    }
+    ids.len()
+}
+
+fn start_message_scheduler(
+    runtime: ConfigRuntime,
+    preset_store: PresetStore,
+    play_log: Arc<PlayLog>,
     tempo_store: TempoStore,
-    config_store: ConfigStore,
 ) {
+    let ConfigRuntime {
+        store,
+        repository,
+        config_store,
+        ..
+    } = &runtime;
+
     loop {
+        apply_pending_config(&runtime, &preset_store);
+
         // Check lamp mode status and set appropriate tempo BEFORE sending
         let is_lamp_mode = send_lamp();
 
@@ -499,7 +2991,19 @@ fn start_message_scheduler(
         drop(config);
 
         // Send the message
-        send_random_message(&store, &morse_converter, &tempo_store, &config_store);
+        let mode = if is_lamp_mode {
+            PlayMode::Lamp
+        } else {
+            PlayMode::Normal
+        };
+        send_random_message(
+            store,
+            repository,
+            &play_log,
+            mode,
+            &tempo_store,
+            config_store,
+        );
 
         // After message ends, check if we just finished lamp mode or should increment counter
         let was_lamp_mode = is_lamp_mode;
@@ -520,27 +3024,37 @@ fn start_message_scheduler(
 
 fn send_random_message(
     store: &MessageStore,
-    morse_converter: &Arc<MorseConverter>,
+    repository: &Repository,
+    play_log: &PlayLog,
+    mode: PlayMode,
     tempo_store: &TempoStore,
     config_store: &ConfigStore,
 ) {
     let selected_message_id = {
         let messages = store.read();
-        if messages.is_empty() {
+        let config = config_store.read();
+        let now = Local::now();
+        let playable: Vec<&Message> = messages
+            .values()
+            .filter(|m| m.status == MessageStatus::Approved && !m.in_trash())
+            .filter(|m| m.lifecycle.state(now.to_utc(), m.send_count) == LifecycleState::Active)
+            .filter(|m| config.tags_playable_at(&m.meta.tags, now.time()))
+            .collect();
+        if playable.is_empty() {
             println!("No messages in pool to send");
             return;
         }
-        let unsent_message_ids: Vec<String> = messages
-            .values()
+        let unsent: Vec<&Message> = playable
+            .iter()
+            .copied()
             .filter(|m| m.last_sent.is_none())
-            .map(|m| m.id.clone())
             .collect();
-        if !unsent_message_ids.is_empty() {
-            unsent_message_ids.choose(&mut rng()).unwrap().clone()
-        } else {
-            let all_ids: Vec<String> = messages.keys().cloned().collect();
-            all_ids.choose(&mut rng()).unwrap().clone()
-        }
+        let candidates = if unsent.is_empty() { playable } else { unsent };
+        // Higher priority messages are picked proportionally more often
+        candidates
+            .choose_weighted(&mut rng(), |m| u32::from(m.meta.priority.max(1)))
+            .map(|m| m.id.clone())
+            .unwrap_or_else(|_| candidates[0].id.clone())
     };
 
     let (message_text, morse_code) = {
@@ -553,12 +3067,38 @@ fn send_random_message(
         }
     };
 
+    let morse_code = {
+        let config = config_store.read();
+        frame_with_prosigns(
+            &morse_code,
+            config.start_prosign.as_deref(),
+            config.end_prosign.as_deref(),
+        )
+    };
+
     let current_tempo = *tempo_store.read();
     println!("Sending message: {message_text}");
     println!("Morse code: {morse_code}");
     println!("Current tempo: {current_tempo} ms");
 
-    send_morse_to_serial(&morse_code, current_tempo, config_store);
+    let started_at = Utc::now();
+    let playback = send_morse_to_serial(&morse_code, current_tempo, config_store);
+    let record = PlayRecord {
+        message_id: selected_message_id.clone(),
+        text: message_text,
+        started_at,
+        ended_at: Utc::now(),
+        tempo_ms: current_tempo,
+        mode,
+        interrupted: playback.interrupted,
+        frame_count: playback.frame_count,
+    };
+    // Nothing reached the lamp, so there is no performance to report
+    if playback.reached_lamp()
+        && let Err(e) = play_log.append(&record)
+    {
+        eprintln!("Failed to write play history: {}", e);
+    }
 
     // After message completes, check if we should exit lamp mode due to completion
     if send_lamp() {
@@ -567,21 +3107,46 @@ fn send_random_message(
     }
 
     let mut messages = store.write();
-    if let Some(message) = messages.get_mut(&selected_message_id) {
-        message.last_sent = Some(Utc::now());
-        message.send_count += 1;
+    if let Some(message) = messages.get_mut(&selected_message_id)
+        && count_play(message, &playback, Utc::now())
+    {
+        log_write_error(repository.upsert(message));
     }
 }
 
-fn send_morse_to_serial(morse_code: &str, tempo_ms: u64, config_store: &ConfigStore) {
-    let mut serial_sender = SerialSender::new("/dev/serial0", 9600).unwrap();
+fn send_morse_to_serial(
+    morse_code: &str,
+    tempo_ms: u64,
+    config_store: &ConfigStore,
+) -> PlaybackResult {
+    let mut result = PlaybackResult {
+        frame_count: 0,
+        interrupted: None,
+    };
+    let mut serial_sender = match SerialSender::new("/dev/serial0", 9600) {
+        Ok(sender) => sender,
+        Err(e) => {
+            eprintln!("Failed to open serial port: {e} - skipping message");
+            result.interrupted = Some(InterruptReason::SerialUnavailable);
+            return result;
+        }
+    };
+    // A skip only applies to the message playing when it was requested
+    SKIP_REQUESTED.store(false, Ordering::SeqCst);
 
     for char in morse_code.chars() {
         // Check for config changes
         if CONFIG_CHANGED.load(Ordering::SeqCst) {
             println!("Config changed - interrupting current message");
             CONFIG_CHANGED.store(false, Ordering::SeqCst);
-            return;
+            result.interrupted = Some(InterruptReason::ConfigChange);
+            return result;
+        }
+
+        if SKIP_REQUESTED.swap(false, Ordering::SeqCst) {
+            println!("Skipping current message");
+            result.interrupted = Some(InterruptReason::Skip);
+            return result;
         }
 
         // Check for lamp mode timeout during message sending
@@ -589,7 +3154,8 @@ fn send_morse_to_serial(morse_code: &str, tempo_ms: u64, config_store: &ConfigSt
             let current_count = CONSECUTIVE_INSTRUMENT_COUNT.load(Ordering::SeqCst);
             if current_count == 0 {
                 println!("Lamp mode timed out during message - stopping current message");
-                return;
+                result.interrupted = Some(InterruptReason::LampTimeout);
+                return result;
             }
         }
 
@@ -600,7 +3166,10 @@ fn send_morse_to_serial(morse_code: &str, tempo_ms: u64, config_store: &ConfigSt
                 let dot_message = convert_dot_message(&config);
                 println!("Sending: {dot_message}");
                 match serial_sender.send_raw(dot_message.as_bytes()) {
-                    Ok(_) => println!("Successfully sent dot via serial!"),
+                    Ok(_) => {
+                        println!("Successfully sent dot via serial!");
+                        result.frame_count += 1;
+                    }
                     Err(e) => eprintln!("Failed to send dot via serial: {e}"),
                 }
                 thread::sleep(Duration::from_millis(tempo_ms));
@@ -609,27 +3178,144 @@ fn send_morse_to_serial(morse_code: &str, tempo_ms: u64, config_store: &ConfigSt
                 let dash_message = convert_dash_message(&config);
                 println!("Sending: {dash_message}");
                 match serial_sender.send_raw(dash_message.as_bytes()) {
-                    Ok(_) => println!("Successfully sent dash via serial!"),
+                    Ok(_) => {
+                        println!("Successfully sent dash via serial!");
+                        result.frame_count += 1;
+                    }
                     Err(e) => eprintln!("Failed to send dash via serial: {e}"),
                 }
-                thread::sleep(Duration::from_millis(tempo_ms * 4));
+                thread::sleep(Duration::from_millis(tempo_ms.saturating_mul(4)));
             }
             ' ' => {
                 let space_message = convert_space_message(&config);
                 println!("Sending: {space_message}");
                 match serial_sender.send_raw(space_message.as_bytes()) {
-                    Ok(_) => println!("Successfully sent space via serial!"),
+                    Ok(_) => {
+                        println!("Successfully sent space via serial!");
+                        result.frame_count += 1;
+                    }
                     Err(e) => eprintln!("Failed to send space via serial: {e}"),
                 }
-                thread::sleep(Duration::from_millis(tempo_ms * 4));
+                thread::sleep(Duration::from_millis(tempo_ms.saturating_mul(4)));
             }
             '\n' => {
-                println!("New line character detected, sleep for 4*tempo");
-                thread::sleep(Duration::from_millis(tempo_ms * 4));
+                // Same pause as the preview timeline, set by the newline policy
+                let pause_ms = element_timing(char, tempo_ms, &config).map_or(0, |(_, ms)| ms);
+                println!("Line break detected, sleep for {} ms", pause_ms);
+                thread::sleep(Duration::from_millis(pause_ms));
             }
             _ => {
                 continue;
             }
         }
     }
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(id: &str, text: &str, morse_code: &str, encoded_with: &str) -> Message {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "text": text,
+            "morse_code": morse_code,
+            "encoded_with": encoded_with,
+            "created_at": "2026-01-01T00:00:00Z",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_unavailable_port_does_not_count_as_play() {
+        let mut played = message("m", "e", ".", "");
+        played.lifecycle.max_sends = Some(1);
+        let unavailable = PlaybackResult {
+            frame_count: 0,
+            interrupted: Some(InterruptReason::SerialUnavailable),
+        };
+        for _ in 0..3 {
+            assert!(!count_play(&mut played, &unavailable, Utc::now()));
+        }
+        assert_eq!(played.send_count, 0);
+        assert_eq!(played.last_sent, None);
+        assert_eq!(
+            played.lifecycle.state(Utc::now(), played.send_count),
+            LifecycleState::Active
+        );
+
+        let skipped_late = PlaybackResult {
+            frame_count: 4,
+            interrupted: Some(InterruptReason::Skip),
+        };
+        assert!(count_play(&mut played, &skipped_late, Utc::now()));
+        assert_eq!(
+            played.lifecycle.state(Utc::now(), played.send_count),
+            LifecycleState::Retired
+        );
+    }
+
+    #[test]
+    fn test_reencode_stale_only_or_all() {
+        let converter = MorseConverter::default();
+        let policy = NewlinePolicy::default();
+        let current = converter.fingerprint(None, policy);
+        let mut messages: HashMap<String, Message> = [
+            message("stale", "e", "-", "v0/old"),
+            message("fresh", "t", ".", &current),
+            message("unchanged", "e", ".", "v0/old"),
+        ]
+        .into_iter()
+        .map(|message| (message.id.clone(), message))
+        .collect();
+
+        let changed = reencode_messages(&mut messages, &converter, policy, true);
+        assert_eq!(changed.len(), 1);
+        assert_eq!(changed[0].id, "stale");
+        assert_eq!(changed[0].old_morse_code, "-");
+        assert_eq!(changed[0].new_morse_code, ".");
+        // Re-encoded without a code change, so only its fingerprint moved
+        assert_eq!(messages["unchanged"].encoded_with, current);
+        assert_eq!(messages["fresh"].morse_code, ".");
+
+        let changed = reencode_messages(&mut messages, &converter, policy, false);
+        let ids: Vec<&str> = changed.iter().map(|change| change.id.as_str()).collect();
+        assert_eq!(ids, ["fresh"]);
+        assert_eq!(messages["fresh"].morse_code, "-");
+    }
+
+    #[test]
+    fn test_auto_save_keeps_reloaded_edit() {
+        let dir = storage::test_dir("auto-save");
+        let path = dir.join("config.json").display().to_string();
+        let config_store: ConfigStore = Arc::new(RwLock::new(TransformerConfig::default()));
+        let pending: PendingConfigStore = Arc::new(RwLock::new(None));
+        save_latest_config(&config_store, &pending, &path);
+        let mut watched = WatchedFile::new(&path, |content| {
+            parse_config(content)
+                .ok()
+                .and_then(|config| serde_json::to_value(config).ok())
+        });
+
+        // Hand edit, picked up by the watcher and waiting for the next message
+        let edited = TransformerConfig {
+            tempo_choices: vec![123],
+            ..TransformerConfig::default()
+        };
+        let content = serde_json::to_string_pretty(&ConfigFile {
+            version: CONFIG_SCHEMA.current_version(),
+            config: &edited,
+        })
+        .unwrap();
+        fs::write(&path, &content).unwrap();
+        let content = watched.poll().unwrap();
+        watched.accept(&content);
+        *pending.write() = Some(edited.clone());
+
+        save_latest_config(&config_store, &pending, &path);
+        let saved = parse_config(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(saved, edited);
+    }
 }
@@ -0,0 +1,269 @@
+use crate::Message;
+use crate::csv;
+use crate::morse_converter::UnsupportedChar;
+use serde::Serialize;
+use unicode_normalization::UnicodeNormalization;
+
+/// Most messages accepted by one import request
+pub const MAX_IMPORT_ITEMS: usize = 5000;
+
+const CSV_HEADER: &str = "id,text,morse_code,alphabet,created_at,last_sent,send_count";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferFormat {
+    Json,
+    Csv,
+    Text,
+}
+
+impl TransferFormat {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "json" => Some(TransferFormat::Json),
+            "csv" => Some(TransferFormat::Csv),
+            "text" | "txt" => Some(TransferFormat::Text),
+            _ => None,
+        }
+    }
+
+    /// Guesses the format of an upload without `?format=`; anything that is
+    /// not JSON or CSV is read as plain text.
+    pub fn from_content_type(content_type: Option<&str>) -> Self {
+        let mime = content_type
+            .and_then(|value| value.split(';').next())
+            .map(|mime| mime.trim().to_ascii_lowercase())
+            .unwrap_or_default();
+        if mime == "application/json" || mime.ends_with("+json") {
+            TransferFormat::Json
+        } else if mime == "text/csv" {
+            TransferFormat::Csv
+        } else {
+            TransferFormat::Text
+        }
+    }
+}
+
+/// One message read from an import file
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportItem {
+    pub text: String,
+    pub alphabet: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportStatus {
+    New,
+    Duplicate,
+    Invalid,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportItemResult {
+    /// Position in the upload, starting at 1
+    pub index: usize,
+    pub text: String,
+    pub status: ImportStatus,
+    /// Id of the stored message; for duplicates, the message it duplicates
+    /// (none when that is an earlier item of a dry run)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub morse_code: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unsupported_chars: Vec<UnsupportedChar>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Reply of `POST /api/messages/import`. With `dry_run` nothing is stored and
+/// `added` counts the messages that would be.
+#[derive(Debug, Clone, Serialize)]
+pub struct ImportReport {
+    pub dry_run: bool,
+    pub total: usize,
+    pub added: usize,
+    pub duplicates: usize,
+    pub invalid: usize,
+    pub items: Vec<ImportItemResult>,
+}
+
+/// Reads the messages of an upload. JSON is an array of strings or of objects
+/// with `text` and optional `alphabet` (so an export can be imported again),
+/// CSV needs a `text` column or has the text in its first column, and plain
+/// text holds one message per line.
+pub fn parse_import(body: &str, format: TransferFormat) -> Result<Vec<ImportItem>, String> {
+    let items = match format {
+        TransferFormat::Json => parse_json(body)?,
+        TransferFormat::Csv => parse_csv(body)?,
+        TransferFormat::Text => body
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(|line| ImportItem {
+                text: line.to_string(),
+                alphabet: None,
+            })
+            .collect(),
+    };
+    if items.len() > MAX_IMPORT_ITEMS {
+        return Err(format!(
+            "{} messages in one import; the limit is {MAX_IMPORT_ITEMS}",
+            items.len()
+        ));
+    }
+    Ok(items)
+}
+
+fn parse_json(body: &str) -> Result<Vec<ImportItem>, String> {
+    let value: serde_json::Value = serde_json::from_str(body).map_err(|e| e.to_string())?;
+    let serde_json::Value::Array(elements) = value else {
+        return Err("expected a JSON array of messages".to_string());
+    };
+    elements
+        .into_iter()
+        .enumerate()
+        .map(|(index, element)| match element {
+            serde_json::Value::String(text) => Ok(ImportItem {
+                text,
+                alphabet: None,
+            }),
+            serde_json::Value::Object(fields) => {
+                let text = fields.get("text").and_then(|v| v.as_str()).ok_or_else(|| {
+                    format!("item {}: expected a string \"text\" field", index + 1)
+                })?;
+                Ok(ImportItem {
+                    text: text.to_string(),
+                    alphabet: fields
+                        .get("alphabet")
+                        .and_then(|v| v.as_str())
+                        .map(str::to_string),
+                })
+            }
+            _ => Err(format!(
+                "item {}: expected a string or an object with \"text\"",
+                index + 1
+            )),
+        })
+        .collect()
+}
+
+fn parse_csv(body: &str) -> Result<Vec<ImportItem>, String> {
+    let mut rows = csv::parse(body)?.into_iter().peekable();
+    let header = rows.peek().map(|row| {
+        let position = |name: &str| {
+            row.iter()
+                .position(|column| column.trim().eq_ignore_ascii_case(name))
+        };
+        (position("text"), position("alphabet"))
+    });
+    let (text_column, alphabet_column) = match header {
+        Some((Some(text), alphabet)) => {
+            rows.next();
+            (text, alphabet)
+        }
+        // No header row: the text is in the first column
+        _ => (0, None),
+    };
+
+    Ok(rows
+        .map(|row| ImportItem {
+            text: row.get(text_column).cloned().unwrap_or_default(),
+            alphabet: alphabet_column
+                .and_then(|column| row.get(column))
+                .filter(|alphabet| !alphabet.is_empty())
+                .cloned(),
+        })
+        .collect())
+}
+
+/// Key under which two messages count as the same: Morse code ignores case,
+/// so texts are compared case-insensitively, after Unicode compatibility
+/// normalization and with runs of whitespace collapsed.
+pub fn dedupe_key(text: &str, alphabet: Option<&str>) -> (String, String) {
+    let text: String = text.nfkc().collect::<String>().to_lowercase();
+    let text = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    (text, alphabet.unwrap_or_default().to_string())
+}
+
+pub fn to_csv(messages: &[Message]) -> String {
+    let mut output = String::from(CSV_HEADER);
+    output.push_str("\r\n");
+    for message in messages {
+        let fields = [
+            message.id.clone(),
+            csv::escape(&message.text),
+            csv::escape(&message.morse_code),
+            csv::escape(message.alphabet.as_deref().unwrap_or_default()),
+            message.created_at.to_rfc3339(),
+            message
+                .last_sent
+                .map(|sent| sent.to_rfc3339())
+                .unwrap_or_default(),
+            message.send_count.to_string(),
+        ];
+        output.push_str(&fields.join(","));
+        output.push_str("\r\n");
+    }
+    output
+}
+
+/// One message per line. Line breaks inside a message become spaces, so this
+/// format does not round-trip multi-line messages.
+pub fn to_text(messages: &[Message]) -> String {
+    messages
+        .iter()
+        .map(|message| {
+            message
+                .text
+                .split_whitespace()
+                .collect::<Vec<_>>()
+                .join(" ")
+                + "\n"
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_import_formats() {
+        let text = parse_import("hello\n\n  world  \n", TransferFormat::Text).unwrap();
+        assert_eq!(
+            text.iter()
+                .map(|item| item.text.as_str())
+                .collect::<Vec<_>>(),
+            ["hello", "world"]
+        );
+
+        let json = r#"["sos", {"text": "こんにちは", "alphabet": "wabun", "send_count": 3}]"#;
+        let json = parse_import(json, TransferFormat::Json).unwrap();
+        assert_eq!(json[1].text, "こんにちは");
+        assert_eq!(json[1].alphabet.as_deref(), Some("wabun"));
+        assert!(parse_import("{}", TransferFormat::Json).is_err());
+
+        // Exported CSV imports again; other columns are ignored
+        let csv = "id,text,morse_code,alphabet\r\n1,\"a, b\",x,\r\n2,c,y,wabun\r\n";
+        let csv = parse_import(csv, TransferFormat::Csv).unwrap();
+        assert_eq!(csv[0].text, "a, b");
+        assert_eq!(csv[0].alphabet, None);
+        assert_eq!(csv[1].alphabet.as_deref(), Some("wabun"));
+
+        let headerless = parse_import("first\nsecond\n", TransferFormat::Csv).unwrap();
+        assert_eq!(headerless.len(), 2);
+    }
+
+    #[test]
+    fn test_dedupe_key_ignores_case_and_spacing() {
+        assert_eq!(
+            dedupe_key("Hello  World", None),
+            dedupe_key(" hello world ", None)
+        );
+        assert_ne!(
+            dedupe_key("hello", None),
+            dedupe_key("hello", Some("wabun"))
+        );
+    }
+}
@@ -0,0 +1,203 @@
+use crate::message_transformer::FieldError;
+use serde::{Deserialize, Serialize};
+use unicode_normalization::UnicodeNormalization;
+
+pub const MIN_PRIORITY: u8 = 1;
+pub const MAX_PRIORITY: u8 = 5;
+pub const DEFAULT_PRIORITY: u8 = 3;
+
+const MAX_TAGS: usize = 16;
+const MAX_TAG_CHARS: usize = 32;
+const MAX_AUTHOR_CHARS: usize = 100;
+const MAX_LANGUAGE_CHARS: usize = 16;
+const MAX_NOTES_CHARS: usize = 2000;
+
+/// Curator-maintained details of a message. Stored flattened into the
+/// message, so every field is optional in existing files.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MessageMeta {
+    /// Normalized with `normalize_tag`, sorted and without duplicates
+    pub tags: Vec<String>,
+    pub author: Option<String>,
+    /// From 1 (rarely) to 5 (often); weights how often the scheduler picks
+    /// the message
+    pub priority: u8,
+    /// Language tag such as `ja` or `en`
+    pub language: Option<String>,
+    /// Free text for curators; never played
+    pub notes: Option<String>,
+}
+
+impl Default for MessageMeta {
+    fn default() -> Self {
+        MessageMeta {
+            tags: Vec::new(),
+            author: None,
+            priority: DEFAULT_PRIORITY,
+            language: None,
+            notes: None,
+        }
+    }
+}
+
+/// Metadata fields of a create or update request. Omitted fields keep their
+/// value; an empty string clears an optional one.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct MetaChanges {
+    pub tags: Option<Vec<String>>,
+    pub author: Option<String>,
+    pub priority: Option<u8>,
+    pub language: Option<String>,
+    pub notes: Option<String>,
+}
+
+impl MessageMeta {
+    /// Applies `changes` if all of them are valid; otherwise leaves the
+    /// metadata untouched and returns every problem.
+    pub fn apply(&mut self, changes: MetaChanges) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+        let mut updated = self.clone();
+
+        if let Some(tags) = changes.tags {
+            let mut normalized: Vec<String> = tags
+                .iter()
+                .map(|tag| normalize_tag(tag))
+                .filter(|tag| !tag.is_empty())
+                .collect();
+            normalized.sort();
+            normalized.dedup();
+            if normalized.len() > MAX_TAGS {
+                errors.push(FieldError::new(
+                    "tags",
+                    format!("at most {MAX_TAGS} tags are allowed"),
+                ));
+            }
+            if normalized
+                .iter()
+                .any(|tag| tag.chars().count() > MAX_TAG_CHARS)
+            {
+                errors.push(FieldError::new(
+                    "tags",
+                    format!("tags must be at most {MAX_TAG_CHARS} characters"),
+                ));
+            }
+            updated.tags = normalized;
+        }
+
+        if let Some(priority) = changes.priority {
+            if !(MIN_PRIORITY..=MAX_PRIORITY).contains(&priority) {
+                errors.push(FieldError::new(
+                    "priority",
+                    format!("must be between {MIN_PRIORITY} and {MAX_PRIORITY}"),
+                ));
+            }
+            updated.priority = priority;
+        }
+
+        if let Some(language) = changes.language {
+            let language = language.trim().to_ascii_lowercase();
+            if language.len() > MAX_LANGUAGE_CHARS
+                || !language
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '-')
+            {
+                errors.push(FieldError::new(
+                    "language",
+                    "must be a language tag such as \"ja\" or \"en-US\"",
+                ));
+            }
+            updated.language = Some(language).filter(|l| !l.is_empty());
+        }
+
+        for (field, change, max_chars, target) in [
+            (
+                "author",
+                changes.author,
+                MAX_AUTHOR_CHARS,
+                &mut updated.author,
+            ),
+            ("notes", changes.notes, MAX_NOTES_CHARS, &mut updated.notes),
+        ] {
+            let Some(value) = change else { continue };
+            let value = value.trim().to_string();
+            if value.chars().count() > max_chars {
+                errors.push(FieldError::new(
+                    field,
+                    format!("must be at most {max_chars} characters"),
+                ));
+            }
+            *target = Some(value).filter(|v| !v.is_empty());
+        }
+
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        *self = updated;
+        Ok(())
+    }
+
+    pub fn has_tag(&self, tag: &str) -> bool {
+        self.tags.iter().any(|t| t == tag)
+    }
+}
+
+/// Canonical form of a tag: compatibility-normalized, lowercase and with
+/// runs of whitespace turned into single hyphens, so "Night Shift" and
+/// "night-shift" are the same tag.
+pub fn normalize_tag(tag: &str) -> String {
+    tag.nfkc()
+        .collect::<String>()
+        .to_lowercase()
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join("-")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply_normalizes_and_rejects_all_or_nothing() {
+        let mut meta = MessageMeta::default();
+        meta.apply(MetaChanges {
+            tags: Some(vec![
+                "Night".to_string(),
+                " night ".to_string(),
+                "Ｌｏｎｇ  Form".to_string(),
+                "".to_string(),
+            ]),
+            author: Some("  Aiko ".to_string()),
+            language: Some("JA".to_string()),
+            ..MetaChanges::default()
+        })
+        .unwrap();
+        assert_eq!(meta.tags, vec!["long-form", "night"]);
+        assert_eq!(meta.author.as_deref(), Some("Aiko"));
+        assert_eq!(meta.language.as_deref(), Some("ja"));
+        assert_eq!(meta.priority, DEFAULT_PRIORITY);
+
+        let before = meta.clone();
+        let errors = meta
+            .apply(MetaChanges {
+                author: Some(String::new()),
+                priority: Some(9),
+                language: Some("日本語".to_string()),
+                ..MetaChanges::default()
+            })
+            .unwrap_err();
+        let fields: Vec<&str> = errors.iter().map(|e| e.field.as_str()).collect();
+        assert_eq!(fields, ["priority", "language"]);
+        assert_eq!(meta, before);
+
+        // An empty string clears the field
+        meta.apply(MetaChanges {
+            author: Some(String::new()),
+            ..MetaChanges::default()
+        })
+        .unwrap();
+        assert_eq!(meta.author, None);
+    }
+}
@@ -0,0 +1,215 @@
+use crate::Message;
+use crate::content_filter::normalize;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+
+/// Most messages one page of `GET /api/messages` may hold
+pub const MAX_PAGE_SIZE: usize = 500;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortKey {
+    #[default]
+    CreatedAt,
+    LastSent,
+    SendCount,
+    Text,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+/// A message's position in a sort. The id breaks ties, so every message
+/// has its own position and pages never overlap or skip one.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+enum SortValue {
+    Time(Option<DateTime<Utc>>),
+    Count(u32),
+    Text(String),
+}
+
+/// Where the next page starts: just past the last message of this one.
+/// Positions rather than offsets keep pages stable while messages are
+/// added or deleted between requests.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cursor {
+    sort: SortKey,
+    order: SortOrder,
+    after: (SortValue, String),
+}
+
+impl Cursor {
+    /// Hex-encoded JSON, so clients can pass it back without escaping
+    pub fn encode(&self) -> String {
+        serde_json::to_vec(self)
+            .unwrap_or_default()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+
+    pub fn decode(value: &str) -> Result<Self, String> {
+        let invalid = || format!("Invalid cursor {value:?}");
+        if !value.len().is_multiple_of(2) || !value.is_ascii() {
+            return Err(invalid());
+        }
+        let bytes = (0..value.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&value[i..i + 2], 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .map_err(|_| invalid())?;
+        serde_json::from_slice(&bytes).map_err(|_| invalid())
+    }
+}
+
+fn sort_value(message: &Message, sort: SortKey) -> SortValue {
+    match sort {
+        SortKey::CreatedAt => SortValue::Time(Some(message.created_at)),
+        SortKey::LastSent => SortValue::Time(message.last_sent),
+        SortKey::SendCount => SortValue::Count(message.send_count),
+        // Kana and width variants sort together
+        SortKey::Text => SortValue::Text(normalize(&message.text)),
+    }
+}
+
+/// Search text prepared once per request
+pub struct Search {
+    normalized: String,
+    lowercase: String,
+}
+
+impl Search {
+    pub fn new(query: &str) -> Self {
+        Search {
+            normalized: normalize(query),
+            lowercase: query.trim().to_lowercase(),
+        }
+    }
+
+    /// Looks in the text, author, notes and tags. Matching ignores case,
+    /// width, katakana versus hiragana, spaces and punctuation; a query of
+    /// only punctuation is matched literally instead.
+    pub fn matches(&self, message: &Message) -> bool {
+        let meta = &message.meta;
+        let mut fields = [
+            Some(message.text.as_str()),
+            meta.author.as_deref(),
+            meta.notes.as_deref(),
+        ]
+        .into_iter()
+        .flatten()
+        .chain(meta.tags.iter().map(String::as_str));
+        if self.normalized.is_empty() {
+            return fields.any(|field| field.to_lowercase().contains(&self.lowercase));
+        }
+        fields.any(|field| normalize(field).contains(&self.normalized))
+    }
+}
+
+/// Sorts `messages` and returns the page after `cursor` with the cursor of
+/// the following page, if there is one. Without a limit the page holds the
+/// rest of the messages.
+pub fn paginate<'a>(
+    messages: Vec<&'a Message>,
+    sort: SortKey,
+    order: SortOrder,
+    cursor: Option<&Cursor>,
+    limit: Option<usize>,
+) -> Result<(Vec<&'a Message>, Option<Cursor>), String> {
+    if let Some(cursor) = cursor
+        && (cursor.sort != sort || cursor.order != order)
+    {
+        return Err("cursor belongs to a different sort or order".to_string());
+    }
+    let mut keyed: Vec<((SortValue, String), &Message)> = messages
+        .into_iter()
+        .map(|message| ((sort_value(message, sort), message.id.clone()), message))
+        .filter(|(position, _)| {
+            cursor.is_none_or(|cursor| match order {
+                SortOrder::Asc => *position > cursor.after,
+                SortOrder::Desc => *position < cursor.after,
+            })
+        })
+        .collect();
+    keyed.sort_by(|(a, _), (b, _)| match order {
+        SortOrder::Asc => a.cmp(b),
+        SortOrder::Desc => b.cmp(a),
+    });
+
+    let next = match limit {
+        Some(limit) if keyed.len() > limit => {
+            keyed.truncate(limit);
+            keyed.last().map(|(position, _)| Cursor {
+                sort,
+                order,
+                after: position.clone(),
+            })
+        }
+        _ => None,
+    };
+    Ok((
+        keyed.into_iter().map(|(_, message)| message).collect(),
+        next,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn message(id: &str, text: &str, send_count: u32) -> Message {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "text": text,
+            "created_at": "2026-01-01T00:00:00Z",
+            "send_count": send_count,
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_pages_follow_cursor_without_gaps() {
+        let messages: Vec<Message> = (0..5)
+            .map(|i| message(&format!("m{i}"), "text", i % 2))
+            .collect();
+        let all: Vec<&Message> = messages.iter().collect();
+
+        let mut seen = Vec::new();
+        let mut cursor = None;
+        loop {
+            let (page, next) = paginate(
+                all.clone(),
+                SortKey::SendCount,
+                SortOrder::Asc,
+                cursor.as_ref(),
+                Some(2),
+            )
+            .unwrap();
+            seen.extend(page.iter().map(|m| m.id.clone()));
+            match next {
+                Some(next) => cursor = Some(Cursor::decode(&next.encode()).unwrap()),
+                None => break,
+            }
+        }
+        assert_eq!(seen, ["m0", "m2", "m4", "m1", "m3"]);
+
+        let cursor = cursor.unwrap();
+        assert!(paginate(all, SortKey::Text, SortOrder::Asc, Some(&cursor), None).is_err());
+        assert!(Cursor::decode("zz").is_err());
+    }
+
+    #[test]
+    fn test_search_ignores_kana_and_width() {
+        let message = message("m", "コンニチハ、世界", 0);
+        for query in ["こんにちは", "ｺﾝﾆﾁﾊ", "にちは世界"] {
+            assert!(Search::new(query).matches(&message), "{query}");
+        }
+        assert!(Search::new("、").matches(&message));
+        assert!(!Search::new("さようなら").matches(&message));
+    }
+}
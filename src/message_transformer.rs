@@ -1,9 +1,15 @@
+use crate::message_meta::normalize_tag;
+use crate::morse_converter::{MAX_PROSIGN_LEN, NewlinePolicy, encode_prosign};
 use crate::send_lamp;
+use chrono::NaiveTime;
 use rand::distr::weighted::WeightedIndex;
 use rand::prelude::*;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+// Missing fields take their default, so older config files keep their settings.
+// Unknown fields are rejected, so a misspelt setting is not silently dropped.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct TransformerConfig {
     // Tempo configuration
     pub tempo_choices: Vec<u64>,
@@ -25,6 +31,72 @@ pub struct TransformerConfig {
     // Lamp probabilities
     pub lamp_probability_when_lamp_mode: f64, // Probability when send_lamp() returns true
     pub lamp_probability_normal: f64,         // Probability when send_lamp() returns false
+
+    // Prosigns sent around every message, e.g. "KA" before and "AR" or "SK" after
+    pub start_prosign: Option<String>,
+    pub end_prosign: Option<String>,
+
+    // Line break handling, shared by message encoding and playback
+    pub newline_policy: NewlinePolicy,
+    pub stanza_break_ms: u64, // Silence for each line break with NewlinePolicy::StanzaBreak
+    pub paragraph_gap_tempos: u64, // Silence for each line break with NewlinePolicy::ParagraphGap, in tempos
+
+    // Messages with these tags only play during the given local hours
+    pub tag_hours: Vec<TagHours>,
+}
+
+/// Local time window for messages carrying `tag`. A window whose end is
+/// before its start runs past midnight, e.g. 18:00 to 06:00.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TagHours {
+    pub tag: String,
+    pub from: NaiveTime,
+    pub until: NaiveTime,
+}
+
+impl TagHours {
+    pub fn contains(&self, time: NaiveTime) -> bool {
+        if self.from <= self.until {
+            // Equal bounds cover the whole day
+            self.from == self.until || (self.from <= time && time < self.until)
+        } else {
+            time >= self.from || time < self.until
+        }
+    }
+}
+
+fn default_stanza_break_ms() -> u64 {
+    8000
+}
+
+fn default_paragraph_gap_tempos() -> u64 {
+    16
+}
+
+const MIN_TEMPO_MS: u64 = 50;
+const MAX_TEMPO_MS: u64 = 10_000;
+const MAX_TEMPO_CHOICES: usize = 32;
+const MAX_WEIGHT: u32 = 1_000_000;
+const MAX_STANZA_BREAK_MS: u64 = 60_000;
+// A word gap " / " plays two 4-tempo space frames
+const WORD_GAP_TEMPOS: u64 = 8;
+const MAX_PARAGRAPH_GAP_TEMPOS: u64 = 64;
+const MAX_TAG_HOURS: usize = 32;
+
+/// A config field that failed validation, e.g. `tempo_choices[1]`.
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldError {
+    pub field: String,
+    pub message: String,
+}
+
+impl FieldError {
+    pub fn new(field: impl Into<String>, message: impl Into<String>) -> Self {
+        FieldError {
+            field: field.into(),
+            message: message.into(),
+        }
+    }
 }
 
 impl Default for TransformerConfig {
@@ -46,10 +118,237 @@ impl Default for TransformerConfig {
 
             lamp_probability_when_lamp_mode: 0.2,
             lamp_probability_normal: 0.1,
+
+            start_prosign: None,
+            end_prosign: None,
+
+            newline_policy: NewlinePolicy::default(),
+            stanza_break_ms: default_stanza_break_ms(),
+            paragraph_gap_tempos: default_paragraph_gap_tempos(),
+
+            tag_hours: Vec::new(),
+        }
+    }
+}
+
+impl TransformerConfig {
+    /// Parses and validates a config sent by a client. Type errors are
+    /// reported per field by parsing each field on top of the defaults.
+    pub fn from_json(value: serde_json::Value) -> Result<Self, Vec<FieldError>> {
+        let error = match serde_json::from_value::<TransformerConfig>(value.clone()) {
+            Ok(config) => return config.validate().map(|_| config),
+            Err(e) => e,
+        };
+
+        let (serde_json::Value::Object(fields), Ok(serde_json::Value::Object(defaults))) =
+            (value, serde_json::to_value(TransformerConfig::default()))
+        else {
+            return Err(vec![FieldError::new("", "expected a JSON object")]);
+        };
+        let errors: Vec<FieldError> = fields
+            .into_iter()
+            .filter_map(|(field, field_value)| {
+                if !defaults.contains_key(&field) {
+                    return Some(FieldError::new(field, "unknown field"));
+                }
+                let mut probe = defaults.clone();
+                probe.insert(field.clone(), field_value);
+                serde_json::from_value::<TransformerConfig>(serde_json::Value::Object(probe))
+                    .err()
+                    .map(|e| FieldError::new(field, e.to_string()))
+            })
+            .collect();
+        if errors.is_empty() {
+            Err(vec![FieldError::new("", error.to_string())])
+        } else {
+            Err(errors)
+        }
+    }
+
+    /// Checks every field against the ranges the transformer and the
+    /// scheduler can handle. Returns all problems, not just the first.
+    pub fn validate(&self) -> Result<(), Vec<FieldError>> {
+        let mut errors = Vec::new();
+
+        let tempo_range = format!("must be between {MIN_TEMPO_MS} and {MAX_TEMPO_MS} ms");
+        if self.tempo_choices.is_empty() {
+            errors.push(FieldError::new(
+                "tempo_choices",
+                "at least one tempo is required",
+            ));
+        } else if self.tempo_choices.len() > MAX_TEMPO_CHOICES {
+            errors.push(FieldError::new(
+                "tempo_choices",
+                format!("at most {MAX_TEMPO_CHOICES} tempos are allowed"),
+            ));
+        }
+        for (i, tempo) in self.tempo_choices.iter().enumerate() {
+            if !(MIN_TEMPO_MS..=MAX_TEMPO_MS).contains(tempo) {
+                errors.push(FieldError::new(
+                    format!("tempo_choices[{i}]"),
+                    tempo_range.clone(),
+                ));
+            }
+        }
+        if !(MIN_TEMPO_MS..=MAX_TEMPO_MS).contains(&self.lamp_tempo_ms) {
+            errors.push(FieldError::new("lamp_tempo_ms", tempo_range));
+        }
+
+        for (field, value) in [
+            (
+                "dot_percussion_probability",
+                self.dot_percussion_probability,
+            ),
+            ("dash_string_probability", self.dash_string_probability),
+            (
+                "lamp_probability_when_lamp_mode",
+                self.lamp_probability_when_lamp_mode,
+            ),
+            ("lamp_probability_normal", self.lamp_probability_normal),
+        ] {
+            if !(0.0..=1.0).contains(&value) {
+                errors.push(FieldError::new(field, "must be between 0.0 and 1.0"));
+            }
+        }
+
+        let weight_groups: [(&str, &[(&str, u32)]); 2] = [
+            (
+                "dot",
+                &[
+                    ("dot_choice_1_weight", self.dot_choice_1_weight),
+                    ("dot_choice_2_weight", self.dot_choice_2_weight),
+                    ("dot_choice_3_weight", self.dot_choice_3_weight),
+                ],
+            ),
+            (
+                "dash",
+                &[
+                    ("dash_choice_1_weight", self.dash_choice_1_weight),
+                    ("dash_choice_2_weight", self.dash_choice_2_weight),
+                    ("dash_choice_3_weight", self.dash_choice_3_weight),
+                    ("dash_choice_4_weight", self.dash_choice_4_weight),
+                ],
+            ),
+        ];
+        for (kind, weights) in weight_groups {
+            for (field, weight) in weights {
+                if *weight > MAX_WEIGHT {
+                    errors.push(FieldError::new(
+                        *field,
+                        format!("must be at most {MAX_WEIGHT}"),
+                    ));
+                }
+            }
+            if weights.iter().all(|(_, weight)| *weight == 0) {
+                for (field, _) in weights {
+                    errors.push(FieldError::new(
+                        *field,
+                        format!("at least one {kind} choice weight must be above 0"),
+                    ));
+                }
+            }
+        }
+
+        for (field, prosign) in [
+            ("start_prosign", &self.start_prosign),
+            ("end_prosign", &self.end_prosign),
+        ] {
+            let Some(letters) = prosign.as_deref().map(str::trim) else {
+                continue;
+            };
+            if letters.is_empty() {
+                continue;
+            }
+            if letters.chars().count() > MAX_PROSIGN_LEN {
+                errors.push(FieldError::new(
+                    field,
+                    format!("must be at most {MAX_PROSIGN_LEN} letters"),
+                ));
+            } else if !letters.chars().all(|c| c.is_ascii_alphanumeric())
+                || encode_prosign(letters).is_none()
+            {
+                errors.push(FieldError::new(
+                    field,
+                    "must only contain the letters A-Z and digits",
+                ));
+            }
+        }
+
+        if self.stanza_break_ms > MAX_STANZA_BREAK_MS {
+            errors.push(FieldError::new(
+                "stanza_break_ms",
+                format!("must be at most {MAX_STANZA_BREAK_MS} ms"),
+            ));
+        }
+
+        if self.paragraph_gap_tempos <= WORD_GAP_TEMPOS
+            || self.paragraph_gap_tempos > MAX_PARAGRAPH_GAP_TEMPOS
+        {
+            errors.push(FieldError::new(
+                "paragraph_gap_tempos",
+                format!(
+                    "must be between {} and {MAX_PARAGRAPH_GAP_TEMPOS}, longer than a word gap",
+                    WORD_GAP_TEMPOS + 1
+                ),
+            ));
+        }
+
+        if self.tag_hours.len() > MAX_TAG_HOURS {
+            errors.push(FieldError::new(
+                "tag_hours",
+                format!("at most {MAX_TAG_HOURS} entries are allowed"),
+            ));
+        }
+        for (i, hours) in self.tag_hours.iter().enumerate() {
+            if normalize_tag(&hours.tag).is_empty() {
+                errors.push(FieldError::new(
+                    format!("tag_hours[{i}].tag"),
+                    "must not be empty",
+                ));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
         }
     }
 }
 
+impl TransformerConfig {
+    /// Whether a message with `tags` may play at local `time`: always when
+    /// none of its tags has configured hours, otherwise while one of their
+    /// windows is open.
+    pub fn tags_playable_at(&self, tags: &[String], time: NaiveTime) -> bool {
+        let mut windows = self
+            .tag_hours
+            .iter()
+            .filter(|hours| tags.contains(&normalize_tag(&hours.tag)))
+            .peekable();
+        windows.peek().is_none() || windows.any(|hours| hours.contains(time))
+    }
+}
+
+/// `random_bool` panics outside 0.0..=1.0; a config loaded from disk is not
+/// validated, so clamp instead.
+fn probability(p: f64) -> f64 {
+    if p.is_nan() { 0.0 } else { p.clamp(0.0, 1.0) }
+}
+
+/// Picks a choice by weight, falling back to the first one when the weights
+/// are unusable (e.g. all zero).
+fn weighted_choice<const N: usize>(
+    choices: [char; N],
+    weights: [u32; N],
+    rng: &mut impl Rng,
+) -> char {
+    match WeightedIndex::new(weights) {
+        Ok(dist) => choices[dist.sample(rng)],
+        Err(_) => choices[0],
+    }
+}
+
 pub fn convert_dot_message(config: &TransformerConfig) -> String {
     let mut rng = rand::rng();
     let mut selected = Vec::new();
@@ -72,10 +371,8 @@ pub fn convert_dot_message(config: &TransformerConfig) -> String {
     } else {
         // Normal mode: percussion instruments
         for _percussion in 1..=12 {
-            if rng.random_bool(config.dot_percussion_probability) {
-                let dist = WeightedIndex::new(weights).unwrap();
-                let choice = choices[dist.sample(&mut rng)];
-                selected.push(choice);
+            if rng.random_bool(probability(config.dot_percussion_probability)) {
+                selected.push(weighted_choice(choices, weights, &mut rng));
             } else {
                 selected.push('0')
             }
@@ -89,9 +386,7 @@ pub fn convert_dot_message(config: &TransformerConfig) -> String {
         // Ensure at least one instrument is active
         if selected[1..=20].iter().all(|&c| c == '0') {
             let idx = rng.random_range(1..=12);
-            let dist = WeightedIndex::new(weights).unwrap();
-            let choice = choices[dist.sample(&mut rng)];
-            selected[idx] = choice;
+            selected[idx] = weighted_choice(choices, weights, &mut rng);
         }
     }
 
@@ -103,17 +398,15 @@ pub fn convert_dot_message(config: &TransformerConfig) -> String {
     };
 
     for _lamp in 1..=6 {
-        if rng.random_bool(lamp_prob) {
+        if rng.random_bool(probability(lamp_prob)) {
             selected.push('1');
         } else {
             selected.push('0');
         }
     }
-    if is_lamp_mode {
-        if selected[21..=26].to_vec().iter().all(|&c| c == '0') {
-            let idx = rng.random_range(21..25);
-            selected[idx] = '1';
-        }
+    if is_lamp_mode && selected[21..=26].to_vec().iter().all(|&c| c == '0') {
+        let idx = rng.random_range(21..25);
+        selected[idx] = '1';
     }
 
     selected.push('>');
@@ -149,10 +442,8 @@ pub fn convert_dash_message(config: &TransformerConfig) -> String {
 
         // String instruments
         for _percussion in 1..=8 {
-            if rng.random_bool(config.dash_string_probability) {
-                let dist = WeightedIndex::new(weights).unwrap();
-                let choice = choices[dist.sample(&mut rng)];
-                selected.push(choice);
+            if rng.random_bool(probability(config.dash_string_probability)) {
+                selected.push(weighted_choice(choices, weights, &mut rng));
             } else {
                 selected.push('0')
             }
@@ -161,9 +452,7 @@ pub fn convert_dash_message(config: &TransformerConfig) -> String {
         // Ensure at least one instrument is active
         if selected[1..=20].iter().all(|&c| c == '0') {
             let idx = rng.random_range(13..=20);
-            let dist = WeightedIndex::new(weights).unwrap();
-            let choice = choices[dist.sample(&mut rng)];
-            selected[idx] = choice;
+            selected[idx] = weighted_choice(choices, weights, &mut rng);
         }
     }
 
@@ -175,17 +464,15 @@ pub fn convert_dash_message(config: &TransformerConfig) -> String {
     };
 
     for _lamp in 1..=6 {
-        if rng.random_bool(lamp_prob) {
+        if rng.random_bool(probability(lamp_prob)) {
             selected.push('2');
         } else {
             selected.push('0');
         }
     }
-    if is_lamp_mode {
-        if selected[21..=26].iter().all(|&c| c == '0') {
-            let idx = rng.random_range(21..25);
-            selected[idx] = '2';
-        }
+    if is_lamp_mode && selected[21..=26].iter().all(|&c| c == '0') {
+        let idx = rng.random_range(21..25);
+        selected[idx] = '2';
     }
 
     selected.push('>');
@@ -203,3 +490,243 @@ pub fn convert_space_message(_config: &TransformerConfig) -> String {
     selected.push('\n');
     selected.iter().collect()
 }
+
+/// One sound or pause of a played message
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MorseElement {
+    Dot,
+    Dash,
+    Gap,
+    LineBreak,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct TimelineEntry {
+    pub element: MorseElement,
+    pub start_ms: u64,
+    pub duration_ms: u64,
+}
+
+/// The element a Morse character plays with its length at `tempo_ms`,
+/// matching the pauses of `send_morse_to_serial`: a dot waits one tempo, a
+/// dash or a gap four, and a line break depends on the newline policy: a
+/// paragraph gap always outlasts the two gaps of a word gap.
+pub fn element_timing(
+    c: char,
+    tempo_ms: u64,
+    config: &TransformerConfig,
+) -> Option<(MorseElement, u64)> {
+    match c {
+        '.' => Some((MorseElement::Dot, tempo_ms)),
+        '-' => Some((MorseElement::Dash, tempo_ms.saturating_mul(4))),
+        ' ' => Some((MorseElement::Gap, tempo_ms.saturating_mul(4))),
+        '\n' if config.newline_policy == NewlinePolicy::StanzaBreak => {
+            Some((MorseElement::LineBreak, config.stanza_break_ms))
+        }
+        '\n' if config.newline_policy == NewlinePolicy::ParagraphGap => Some((
+            MorseElement::LineBreak,
+            tempo_ms.saturating_mul(config.paragraph_gap_tempos),
+        )),
+        '\n' => Some((MorseElement::LineBreak, tempo_ms.saturating_mul(4))),
+        _ => None,
+    }
+}
+
+/// How long playing `morse_code` at `tempo_ms` takes
+pub fn playback_duration_ms(morse_code: &str, tempo_ms: u64, config: &TransformerConfig) -> u64 {
+    morse_code
+        .chars()
+        .filter_map(|c| element_timing(c, tempo_ms, config))
+        .map(|(_, duration_ms)| duration_ms)
+        .fold(0, u64::saturating_add)
+}
+
+/// Every element of `morse_code` with when it starts and how long it lasts
+pub fn playback_timeline(
+    morse_code: &str,
+    tempo_ms: u64,
+    config: &TransformerConfig,
+) -> Vec<TimelineEntry> {
+    let mut start_ms = 0u64;
+    morse_code
+        .chars()
+        .filter_map(|c| element_timing(c, tempo_ms, config))
+        .map(|(element, duration_ms)| {
+            let entry = TimelineEntry {
+                element,
+                start_ms,
+                duration_ms,
+            };
+            start_ms = start_ms.saturating_add(duration_ms);
+            entry
+        })
+        .collect()
+}
+
+/// The serial frame sent for an element; line breaks only pause. Frames
+/// are drawn at random, so two calls rarely return the same one.
+pub fn element_frame(element: MorseElement, config: &TransformerConfig) -> Option<String> {
+    match element {
+        MorseElement::Dot => Some(convert_dot_message(config)),
+        MorseElement::Dash => Some(convert_dash_message(config)),
+        MorseElement::Gap => Some(convert_space_message(config)),
+        MorseElement::LineBreak => None,
+    }
+}
+
+/// Slowest tempo the scheduler may pick, normal or lamp mode.
+pub fn slowest_tempo_ms(config: &TransformerConfig) -> u64 {
+    config
+        .tempo_choices
+        .iter()
+        .copied()
+        .chain([config.lamp_tempo_ms])
+        .max()
+        .unwrap_or(config.lamp_tempo_ms)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn fields(errors: &[FieldError]) -> Vec<&str> {
+        errors.iter().map(|e| e.field.as_str()).collect()
+    }
+
+    #[test]
+    fn test_validate_reports_every_bad_field() {
+        assert!(TransformerConfig::default().validate().is_ok());
+
+        let config = TransformerConfig {
+            tempo_choices: vec![700, 0],
+            dot_percussion_probability: 1.5,
+            dash_choice_1_weight: 0,
+            dash_choice_2_weight: 0,
+            dash_choice_3_weight: 0,
+            dash_choice_4_weight: 0,
+            start_prosign: Some("<SK>".to_string()),
+            ..TransformerConfig::default()
+        };
+        let errors = config.validate().unwrap_err();
+        assert_eq!(
+            fields(&errors),
+            vec![
+                "tempo_choices[1]",
+                "dot_percussion_probability",
+                "dash_choice_1_weight",
+                "dash_choice_2_weight",
+                "dash_choice_3_weight",
+                "dash_choice_4_weight",
+                "start_prosign",
+            ]
+        );
+
+        let empty = TransformerConfig {
+            tempo_choices: vec![],
+            ..TransformerConfig::default()
+        };
+        assert_eq!(
+            fields(&empty.validate().unwrap_err()),
+            vec!["tempo_choices"]
+        );
+    }
+
+    #[test]
+    fn test_from_json_names_fields_with_wrong_types() {
+        let errors = TransformerConfig::from_json(json!({
+            "lamp_tempo_ms": "fast",
+            "lamp_probability_normal": 0.5,
+        }))
+        .unwrap_err();
+        assert_eq!(fields(&errors), vec!["lamp_tempo_ms"]);
+
+        let errors = TransformerConfig::from_json(json!({
+            "lamp_tempo": 300,
+            "dot_choice_1_weight": -1,
+        }))
+        .unwrap_err();
+        assert_eq!(fields(&errors), vec!["dot_choice_1_weight", "lamp_tempo"]);
+        assert_eq!(errors[1].message, "unknown field");
+
+        let config = TransformerConfig::from_json(json!({ "lamp_tempo_ms": 300 })).unwrap();
+        assert_eq!(config.lamp_tempo_ms, 300);
+    }
+
+    #[test]
+    fn test_bad_config_does_not_panic() {
+        let config = TransformerConfig {
+            dot_percussion_probability: f64::NAN,
+            dash_string_probability: 7.0,
+            dot_choice_1_weight: 0,
+            dot_choice_2_weight: 0,
+            dot_choice_3_weight: 0,
+            lamp_probability_normal: -1.0,
+            ..TransformerConfig::default()
+        };
+        assert_eq!(convert_dot_message(&config).len(), 29);
+        assert_eq!(convert_dash_message(&config).len(), 29);
+    }
+
+    #[test]
+    fn test_tag_hours_limit_tagged_messages() {
+        let time = |t: &str| NaiveTime::parse_from_str(t, "%H:%M").unwrap();
+        let config = TransformerConfig {
+            tag_hours: vec![TagHours {
+                tag: "Night".to_string(),
+                from: time("18:00"),
+                until: time("06:00"),
+            }],
+            ..TransformerConfig::default()
+        };
+        let night = vec!["night".to_string()];
+        let other = vec!["morning".to_string()];
+
+        assert!(config.tags_playable_at(&night, time("23:30")));
+        assert!(config.tags_playable_at(&night, time("05:59")));
+        assert!(!config.tags_playable_at(&night, time("12:00")));
+        assert!(config.tags_playable_at(&other, time("12:00")));
+        assert!(config.tags_playable_at(&[], time("12:00")));
+    }
+
+    #[test]
+    fn test_timeline_adds_up_to_playback_duration() {
+        let config = TransformerConfig {
+            newline_policy: NewlinePolicy::StanzaBreak,
+            stanza_break_ms: 5000,
+            ..TransformerConfig::default()
+        };
+        let timeline = playback_timeline(".- x\n.", 100, &config);
+        let elements: Vec<(MorseElement, u64, u64)> = timeline
+            .iter()
+            .map(|e| (e.element, e.start_ms, e.duration_ms))
+            .collect();
+        assert_eq!(
+            elements,
+            [
+                (MorseElement::Dot, 0, 100),
+                (MorseElement::Dash, 100, 400),
+                (MorseElement::Gap, 500, 400),
+                (MorseElement::LineBreak, 900, 5000),
+                (MorseElement::Dot, 5900, 100),
+            ]
+        );
+        assert_eq!(playback_duration_ms(".- x\n.", 100, &config), 6000);
+    }
+
+    #[test]
+    fn test_paragraph_gap_outlasts_word_gap() {
+        let config = TransformerConfig::default();
+        assert_eq!(config.newline_policy, NewlinePolicy::ParagraphGap);
+        let word_gap = playback_duration_ms(" / ", 100, &config);
+        let paragraph_gap = playback_duration_ms("\n", 100, &config);
+        assert!(paragraph_gap > word_gap, "{paragraph_gap} <= {word_gap}");
+
+        let config = TransformerConfig {
+            paragraph_gap_tempos: WORD_GAP_TEMPOS,
+            ..TransformerConfig::default()
+        };
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,127 @@
+use serde_json::Value;
+
+/// Upgrades the JSON of one schema version to the next.
+type Step = fn(Value) -> Result<Value, String>;
+
+/// Version history of a persisted file. Files carry their version in a
+/// top-level `version` field; files written before versioning have none and
+/// count as version 1.
+pub struct Schema {
+    pub name: &'static str,
+    /// `steps[i]` upgrades version `i + 1` to `i + 2`
+    steps: &'static [Step],
+}
+
+impl Schema {
+    pub fn current_version(&self) -> u32 {
+        self.steps.len() as u32 + 1
+    }
+
+    pub fn version_of(&self, value: &Value) -> u32 {
+        value
+            .get("version")
+            .and_then(Value::as_u64)
+            .map_or(1, |v| (v as u32).max(1))
+    }
+
+    /// Runs every step from the version of `value` up to the current one and
+    /// returns the upgraded value with the version it started from. Values
+    /// from a newer version are returned unchanged.
+    pub fn migrate(&self, mut value: Value) -> Result<(Value, u32), String> {
+        let found = self.version_of(&value);
+        if found > self.current_version() {
+            eprintln!(
+                "WARNING: {} was written by a newer version (schema {} > {}); unknown fields will be dropped on save",
+                self.name,
+                found,
+                self.current_version()
+            );
+            return Ok((value, found));
+        }
+
+        for version in found..self.current_version() {
+            value = (self.steps[version as usize - 1])(value).map_err(|e| {
+                format!(
+                    "{} migration v{} -> v{}: {}",
+                    self.name,
+                    version,
+                    version + 1,
+                    e
+                )
+            })?;
+            match value.as_object_mut() {
+                Some(object) => {
+                    object.insert("version".to_string(), Value::from(version + 1));
+                }
+                None => {
+                    return Err(format!(
+                        "{} v{} is not a JSON object",
+                        self.name,
+                        version + 1
+                    ));
+                }
+            }
+        }
+        Ok((value, found))
+    }
+}
+
+pub const MESSAGES_SCHEMA: Schema = Schema {
+    name: "messages",
+    steps: &[messages_v1_to_v2],
+};
+
+pub const CONFIG_SCHEMA: Schema = Schema {
+    name: "transformer config",
+    steps: &[config_v1_to_v2],
+};
+
+pub const PRESETS_SCHEMA: Schema = Schema {
+    name: "config presets",
+    steps: &[],
+};
+
+/// v1 was the bare id -> message map; v2 wraps it as `{version, messages}`.
+fn messages_v1_to_v2(value: Value) -> Result<Value, String> {
+    if !value.is_object() {
+        return Err("expected a map of messages".to_string());
+    }
+    Ok(serde_json::json!({ "messages": value }))
+}
+
+/// Only the version field is new; fields added since v1 take their defaults
+/// when the config is parsed.
+fn config_v1_to_v2(value: Value) -> Result<Value, String> {
+    Ok(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn test_unversioned_messages_are_wrapped() {
+        let legacy = json!({ "a": { "id": "a", "text": "hi" } });
+        let (migrated, found) = MESSAGES_SCHEMA.migrate(legacy.clone()).unwrap();
+
+        assert_eq!(found, 1);
+        assert_eq!(migrated["version"], json!(2));
+        assert_eq!(migrated["messages"], legacy);
+
+        // Already current: untouched
+        let (again, found) = MESSAGES_SCHEMA.migrate(migrated.clone()).unwrap();
+        assert_eq!(found, 2);
+        assert_eq!(again, migrated);
+    }
+
+    #[test]
+    fn test_newer_versions_are_left_alone() {
+        let future = json!({ "version": 99, "tempo_choices": [400] });
+        let (value, found) = CONFIG_SCHEMA.migrate(future.clone()).unwrap();
+        assert_eq!(found, 99);
+        assert_eq!(value, future);
+
+        assert!(MESSAGES_SCHEMA.migrate(json!([1, 2])).is_err());
+    }
+}
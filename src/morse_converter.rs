@@ -1,10 +1,83 @@
+use crate::code_table::{CodeTable, CodeTableRegistry};
 use japanese::{charset, converter};
 use ripmors::encode_string;
+use serde::{Deserialize, Serialize};
 
-pub struct MorseConverter {}
+/// Bump when the encoder output changes for the same text and options, so
+/// stored messages get re-encoded on the next start.
+pub const CONVERTER_VERSION: u32 = 1;
+
+#[derive(Default)]
+pub struct MorseConverter {
+    pub tables: CodeTableRegistry,
+}
+
+/// A character that has no Morse representation and is dropped from the output.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct UnsupportedChar {
+    pub character: char,
+    /// Index of the character (not byte offset) in the original text.
+    pub position: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct ConversionResult {
+    pub morse_code: String,
+    pub unsupported: Vec<UnsupportedChar>,
+    /// Fingerprint of the converter version and options that produced `morse_code`
+    pub encoded_with: String,
+}
+
+/// How line breaks in message text are encoded and played back.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum NewlinePolicy {
+    /// A line break is an ordinary word gap
+    WordGap,
+    /// Line breaks become one paragraph pause; blank lines are collapsed
+    #[default]
+    ParagraphGap,
+    /// Every line break is kept, blank lines included, and played as a longer silence
+    StanzaBreak,
+}
+
+#[derive(Debug)]
+pub enum ConversionError {
+    UnknownAlphabet(String),
+}
+
+impl std::fmt::Display for ConversionError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConversionError::UnknownAlphabet(name) => write!(f, "Unknown alphabet: {name}"),
+        }
+    }
+}
+
+impl std::error::Error for ConversionError {}
 
 impl MorseConverter {
-    pub fn morse_converter(&self, text: &str) -> String {
+    pub fn new(tables: CodeTableRegistry) -> Self {
+        MorseConverter { tables }
+    }
+
+    /// Encodes `text` with the standard encoder, or with the named code table
+    /// when `alphabet` is given.
+    pub fn convert(
+        &self,
+        text: &str,
+        alphabet: Option<&str>,
+        newline_policy: NewlinePolicy,
+    ) -> Result<ConversionResult, ConversionError> {
+        let table = match alphabet {
+            Some(name) => Some(
+                self.tables
+                    .get(name)
+                    .ok_or_else(|| ConversionError::UnknownAlphabet(name.to_string()))?,
+            ),
+            None => None,
+        };
+
         let mut katakana_text = String::with_capacity(text.len());
 
         if !text.chars().all(charset::is_kana) {
@@ -22,25 +95,284 @@ impl MorseConverter {
         }
 
         println!("Original text: {text}, Converted text: {katakana_text}");
-        encode_string(&katakana_text.to_string()) // Encode all of them
+
+        let mut morse_code = String::new();
+        let mut unsupported = Vec::new();
+        for segment in split_prosigns(&katakana_text) {
+            let code = match segment {
+                Segment::Text { offset, text } => {
+                    let result = encode_text(text, table);
+                    unsupported.extend(result.unsupported.into_iter().map(|u| UnsupportedChar {
+                        character: u.character,
+                        position: u.position + offset,
+                    }));
+                    result.morse_code
+                }
+                // Letters were checked to be encodable when splitting
+                Segment::Prosign(letters) => encode_prosign(letters).unwrap_or_default(),
+            };
+            if code.is_empty() {
+                continue;
+            }
+            if !morse_code.is_empty() && !morse_code.ends_with('\n') && !code.starts_with('\n') {
+                morse_code.push(' ');
+            }
+            morse_code.push_str(&code);
+        }
+
+        Ok(ConversionResult {
+            morse_code: apply_newline_policy(&morse_code, newline_policy),
+            unsupported,
+            encoded_with: self.fingerprint(alphabet, newline_policy),
+        })
+    }
+
+    /// Identifies the encoder output for the given options. Messages whose
+    /// stored fingerprint differs are stale and need re-encoding.
+    pub fn fingerprint(&self, alphabet: Option<&str>, newline_policy: NewlinePolicy) -> String {
+        match alphabet.and_then(|name| self.tables.get(name)) {
+            Some(table) => format!(
+                "v{CONVERTER_VERSION}/{newline_policy:?}/{}:{:016x}",
+                table.name,
+                table.digest()
+            ),
+            None => format!("v{CONVERTER_VERSION}/{newline_policy:?}/standard"),
+        }
+    }
+}
+
+/// Rewrites the line breaks of encoded Morse according to `policy`. Working on
+/// the Morse output keeps unsupported character positions relative to the
+/// text the editor typed.
+fn apply_newline_policy(morse_code: &str, policy: NewlinePolicy) -> String {
+    let morse_code = morse_code.replace("\r\n", "\n").replace('\r', "\n");
+    let lines = morse_code
+        .split('\n')
+        .map(|line| line.trim_matches(|c| c == ' ' || c == '/'));
+
+    match policy {
+        NewlinePolicy::WordGap => lines
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join(" / "),
+        NewlinePolicy::ParagraphGap => lines
+            .filter(|line| !line.is_empty())
+            .collect::<Vec<_>>()
+            .join("\n"),
+        NewlinePolicy::StanzaBreak => lines
+            .collect::<Vec<_>>()
+            .join("\n")
+            .trim_matches('\n')
+            .to_string(),
+    }
+}
+
+fn encode_text(text: &str, table: Option<&CodeTable>) -> ConversionResult {
+    if let Some(table) = table {
+        return table.encode(text);
+    }
+
+    // The katakana conversion is one-to-one, so positions map back to `text`
+    let unsupported = text
+        .chars()
+        .enumerate()
+        .filter(|(_, c)| !c.is_whitespace() && encode_string(&c.to_string()).is_empty())
+        .map(|(position, character)| UnsupportedChar {
+            character,
+            position,
+        })
+        .collect();
+
+    ConversionResult {
+        morse_code: encode_string(text), // Encode all of them
+        unsupported,
+        encoded_with: String::new(),
+    }
+}
+
+enum Segment<'a> {
+    /// Plain text starting at character index `offset` of the message
+    Text {
+        offset: usize,
+        text: &'a str,
+    },
+    Prosign(&'a str),
+}
+
+/// Splits `<SK>`-style prosign markup out of the text. Anything between angle
+/// brackets that is not a short run of encodable letters stays plain text.
+fn split_prosigns(text: &str) -> Vec<Segment<'_>> {
+    let mut segments = Vec::new();
+    let mut text_start = 0;
+    let mut search_from = 0;
+
+    while let Some(open) = text[search_from..].find('<').map(|i| i + search_from) {
+        let candidate = text[open + 1..]
+            .find('>')
+            .map(|close| &text[open + 1..open + 1 + close])
+            .filter(|letters| {
+                (1..=MAX_PROSIGN_LEN).contains(&letters.len())
+                    && letters.chars().all(|c| c.is_ascii_alphanumeric())
+            });
+
+        match candidate {
+            Some(letters) => {
+                if open > text_start {
+                    segments.push(Segment::Text {
+                        offset: text[..text_start].chars().count(),
+                        text: &text[text_start..open],
+                    });
+                }
+                segments.push(Segment::Prosign(letters));
+                text_start = open + letters.len() + 2;
+                search_from = text_start;
+            }
+            None => search_from = open + 1,
+        }
+    }
+
+    if text_start < text.len() {
+        segments.push(Segment::Text {
+            offset: text[..text_start].chars().count(),
+            text: &text[text_start..],
+        });
     }
+    segments
+}
+
+pub const MAX_PROSIGN_LEN: usize = 8;
+
+/// Encodes letters such as `SK` as a single run-together character with no
+/// inter-letter gaps. Returns `None` if any letter has no Morse code.
+pub fn encode_prosign(letters: &str) -> Option<String> {
+    let letters = letters.trim();
+    if letters.is_empty() {
+        return None;
+    }
+    letters
+        .chars()
+        .map(|c| Some(encode_string(&c.to_string())).filter(|code| !code.is_empty()))
+        .collect()
+}
+
+/// Surrounds an encoded message with the configured start and end prosigns.
+pub fn frame_with_prosigns(
+    morse_code: &str,
+    start_prosign: Option<&str>,
+    end_prosign: Option<&str>,
+) -> String {
+    let mut parts = Vec::new();
+    if let Some(code) = start_prosign.and_then(encode_prosign) {
+        parts.push(code);
+    }
+    if !morse_code.is_empty() {
+        parts.push(morse_code.to_string());
+    }
+    if let Some(code) = end_prosign.and_then(encode_prosign) {
+        parts.push(code);
+    }
+    parts.join(" ")
 }
 #[cfg(test)]
 mod tests {
     use super::*;
-    use japanese::{charset, converter};
     use ripmors::encode_string;
 
     #[test]
     fn test_morse_converter_with_katakana() {
-        let converter = MorseConverter {};
+        let converter = MorseConverter::default();
         let input = "hello\nhi"; // already Katakana
-        let output = converter.morse_converter(input);
+        let output = converter
+            .convert(input, None, NewlinePolicy::default())
+            .unwrap()
+            .morse_code;
 
         // The result should not be empty
         assert!(!output.is_empty(), "Morse code should not be empty");
 
         // Ensure encode_string does something meaningful
-        assert_eq!(output, encode_string(&input.to_string()));
+        assert_eq!(output, encode_string(input));
+    }
+
+    #[test]
+    fn test_convert_reports_unsupported_characters() {
+        let converter = MorseConverter::default();
+        let result = converter
+            .convert("アイ 漢字\nok", None, NewlinePolicy::default())
+            .unwrap();
+
+        assert_eq!(
+            result.unsupported,
+            vec![
+                UnsupportedChar {
+                    character: '漢',
+                    position: 3
+                },
+                UnsupportedChar {
+                    character: '字',
+                    position: 4
+                },
+            ]
+        );
+        assert!(!result.morse_code.is_empty());
+    }
+
+    #[test]
+    fn test_convert_with_builtin_alphabets() {
+        let converter = MorseConverter::default();
+
+        let cyrillic = converter
+            .convert("да нет", Some("cyrillic"), NewlinePolicy::default())
+            .unwrap();
+        assert_eq!(cyrillic.morse_code, encode_string("да нет"));
+
+        // Hangul syllables are decomposed into jamo: 한 = ㅎ ㅏ ㄴ
+        let korean = converter
+            .convert("한", Some("korean_skats"), NewlinePolicy::default())
+            .unwrap();
+        assert_eq!(korean.morse_code, ".--- . ..-.");
+        assert!(korean.unsupported.is_empty());
+
+        assert!(
+            converter
+                .convert("abc", Some("klingon"), NewlinePolicy::default())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_prosign_markup_is_run_together() {
+        let converter = MorseConverter::default();
+
+        let result = converter
+            .convert("<KA>hi <AR>", None, NewlinePolicy::default())
+            .unwrap();
+        assert_eq!(result.morse_code, "-.-.- .... .. / .-.-.");
+
+        // Not a prosign: left as text, and the unsupported position is kept
+        let result = converter
+            .convert("<a b>漢", None, NewlinePolicy::default())
+            .unwrap();
+        assert_eq!(result.unsupported[0].position, 5);
+
+        assert_eq!(
+            frame_with_prosigns(".-", Some("KA"), Some("SK")),
+            "-.-.- .- ...-.-"
+        );
+    }
+
+    #[test]
+    fn test_newline_policies() {
+        let converter = MorseConverter::default();
+        let convert = |policy| {
+            converter
+                .convert("a\r\nb\n\nc ", None, policy)
+                .unwrap()
+                .morse_code
+        };
+
+        assert_eq!(convert(NewlinePolicy::WordGap), ".- / -... / -.-.");
+        assert_eq!(convert(NewlinePolicy::ParagraphGap), ".-\n-...\n-.-.");
+        assert_eq!(convert(NewlinePolicy::StanzaBreak), ".-\n-...\n\n-.-.");
     }
 }
@@ -0,0 +1,181 @@
+use crate::csv;
+use crate::storage;
+use chrono::{DateTime, Utc};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::io;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PlayMode {
+    Normal,
+    Lamp,
+}
+
+/// Why a performance stopped before the end of the message.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InterruptReason {
+    ConfigChange,
+    LampTimeout,
+    Skip,
+    /// Nothing was sent, so such plays are not logged; older logs may
+    /// still hold it
+    SerialUnavailable,
+}
+
+/// One performance of a message, as written to the play log.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlayRecord {
+    pub message_id: String,
+    // Text at the time of playing, so the record survives edits and deletes
+    pub text: String,
+    pub started_at: DateTime<Utc>,
+    pub ended_at: DateTime<Utc>,
+    pub tempo_ms: u64,
+    pub mode: PlayMode,
+    pub interrupted: Option<InterruptReason>,
+    // Dot, dash and space frames written to the serial port
+    pub frame_count: u32,
+}
+
+const CSV_HEADER: &str =
+    "started_at,ended_at,message_id,text,tempo_ms,mode,interrupted,frame_count";
+
+/// Append-only log of every performance, one JSON record per line.
+pub struct PlayLog {
+    file_path: String,
+    write_lock: Mutex<()>,
+}
+
+impl PlayLog {
+    pub fn new(file_path: &str) -> Self {
+        PlayLog {
+            file_path: file_path.to_string(),
+            write_lock: Mutex::new(()),
+        }
+    }
+
+    pub fn append(&self, record: &PlayRecord) -> io::Result<()> {
+        let _guard = self.write_lock.lock();
+        storage::append_json_line(&self.file_path, record)
+    }
+
+    /// Records that started within `[from, until)`, oldest first.
+    pub fn query(
+        &self,
+        from: Option<DateTime<Utc>>,
+        until: Option<DateTime<Utc>>,
+    ) -> io::Result<Vec<PlayRecord>> {
+        let mut records: Vec<PlayRecord> = storage::read_json_lines(&self.file_path)?;
+        records.retain(|record| {
+            from.is_none_or(|from| record.started_at >= from)
+                && until.is_none_or(|until| record.started_at < until)
+        });
+        records.sort_by_key(|record| record.started_at);
+        Ok(records)
+    }
+}
+
+pub fn to_csv(records: &[PlayRecord]) -> String {
+    let mut csv = String::from(CSV_HEADER);
+    csv.push_str("\r\n");
+    for record in records {
+        let fields = [
+            record.started_at.to_rfc3339(),
+            record.ended_at.to_rfc3339(),
+            record.message_id.clone(),
+            csv::escape(&record.text),
+            record.tempo_ms.to_string(),
+            enum_name(&record.mode),
+            record
+                .interrupted
+                .as_ref()
+                .map(enum_name)
+                .unwrap_or_default(),
+            record.frame_count.to_string(),
+        ];
+        csv.push_str(&fields.join(","));
+        csv.push_str("\r\n");
+    }
+    csv
+}
+
+fn enum_name<T: Serialize>(value: &T) -> String {
+    serde_json::to_value(value)
+        .ok()
+        .and_then(|v| v.as_str().map(str::to_string))
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use std::fs::OpenOptions;
+    use std::io::Write;
+
+    fn record(id: &str, hour: u32) -> PlayRecord {
+        let started_at = Utc.with_ymd_and_hms(2026, 5, 1, hour, 0, 0).unwrap();
+        PlayRecord {
+            message_id: id.to_string(),
+            text: format!("text of {id}"),
+            started_at,
+            ended_at: started_at + chrono::Duration::seconds(30),
+            tempo_ms: 400,
+            mode: PlayMode::Normal,
+            interrupted: None,
+            frame_count: 12,
+        }
+    }
+
+    fn ids(records: &[PlayRecord]) -> Vec<&str> {
+        records.iter().map(|r| r.message_id.as_str()).collect()
+    }
+
+    #[test]
+    fn test_append_survives_torn_line_and_filters_by_start() {
+        let dir = storage::test_dir("play-log");
+        let path = dir.join("play_history.jsonl").display().to_string();
+        let log = PlayLog::new(&path);
+        assert!(log.query(None, None).unwrap().is_empty());
+
+        log.append(&record("late", 12)).unwrap();
+        log.append(&record("early", 8)).unwrap();
+        // A power cut in the middle of a write
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(br#"{"message_id":"torn","te"#).unwrap();
+        drop(file);
+        let mut interrupted = record("noon", 10);
+        interrupted.interrupted = Some(InterruptReason::Skip);
+        log.append(&interrupted).unwrap();
+
+        let all = log.query(None, None).unwrap();
+        assert_eq!(ids(&all), ["early", "noon", "late"]);
+        assert_eq!(all[1].interrupted, Some(InterruptReason::Skip));
+
+        // `from` is inclusive, `until` exclusive
+        let from = Some(Utc.with_ymd_and_hms(2026, 5, 1, 10, 0, 0).unwrap());
+        let until = Some(Utc.with_ymd_and_hms(2026, 5, 1, 12, 0, 0).unwrap());
+        assert_eq!(ids(&log.query(from, None).unwrap()), ["noon", "late"]);
+        assert_eq!(ids(&log.query(None, until).unwrap()), ["early", "noon"]);
+        assert_eq!(ids(&log.query(from, until).unwrap()), ["noon"]);
+    }
+
+    #[test]
+    fn test_csv_quotes_text_and_names_enums() {
+        let mut played = record("m1", 9);
+        played.text = "Hello, \"world\"".to_string();
+        played.mode = PlayMode::Lamp;
+        played.interrupted = Some(InterruptReason::LampTimeout);
+
+        let csv = to_csv(&[played]);
+        let lines: Vec<&str> = csv.split("\r\n").collect();
+        assert_eq!(lines[0], CSV_HEADER);
+        assert_eq!(
+            lines[1],
+            "2026-05-01T09:00:00+00:00,2026-05-01T09:00:30+00:00,m1,\"Hello, \"\"world\"\"\",400,lamp,lamp_timeout,12"
+        );
+        assert_eq!(lines[2], "");
+    }
+}
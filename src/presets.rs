@@ -0,0 +1,384 @@
+use crate::message_transformer::{FieldError, TransformerConfig};
+use crate::migration::PRESETS_SCHEMA;
+use crate::storage::{self, LoadStatus};
+use chrono::{DateTime, Local, NaiveTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+const MAX_NAME_LEN: usize = 64;
+
+#[derive(Debug)]
+pub enum PresetError {
+    NotFound(String),
+    AlreadyExists(String),
+    InvalidName(String),
+    /// The preset cannot be deleted while schedule entries refer to it
+    Scheduled(String),
+    InvalidSchedule(String),
+    ScheduleNotFound(String),
+    InvalidConfig(Vec<FieldError>),
+}
+
+impl std::fmt::Display for PresetError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PresetError::NotFound(name) => write!(f, "Preset not found: {name}"),
+            PresetError::AlreadyExists(name) => write!(f, "Preset already exists: {name}"),
+            PresetError::InvalidName(reason) => write!(f, "Invalid preset name: {reason}"),
+            PresetError::Scheduled(name) => {
+                write!(
+                    f,
+                    "Preset {name} is scheduled; remove its schedule entries first"
+                )
+            }
+            PresetError::InvalidSchedule(reason) => write!(f, "Invalid schedule: {reason}"),
+            PresetError::ScheduleNotFound(id) => write!(f, "Schedule entry not found: {id}"),
+            PresetError::InvalidConfig(_) => write!(f, "Preset config is invalid"),
+        }
+    }
+}
+
+impl std::error::Error for PresetError {}
+
+/// A named `TransformerConfig` operators can switch to.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Preset {
+    pub name: String,
+    pub config: TransformerConfig,
+    pub created_at: DateTime<Utc>,
+    pub updated_at: DateTime<Utc>,
+}
+
+/// A planned switch to a preset, either every day at a local time of day or
+/// once at a given instant. One-off entries are removed once applied.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduleEntry {
+    pub id: String,
+    pub preset: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub daily_at: Option<NaiveTime>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub at: Option<DateTime<Utc>>,
+}
+
+impl ScheduleEntry {
+    /// The moment this entry fires within `(since, now]`, if any
+    fn due_within(&self, since: DateTime<Local>, now: DateTime<Local>) -> Option<DateTime<Local>> {
+        if let Some(at) = self.at {
+            let at = at.with_timezone(&Local);
+            return (at <= now).then_some(at);
+        }
+        let time = self.daily_at?;
+        // Checking today and yesterday covers a check interval spanning midnight
+        [now.date_naive(), now.date_naive().pred_opt()?]
+            .into_iter()
+            .filter_map(|day| day.and_time(time).and_local_timezone(Local).earliest())
+            .find(|fire| *fire > since && *fire <= now)
+    }
+}
+
+/// Saved presets, the preset schedule and the preset currently applied.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PresetBook {
+    #[serde(default)]
+    pub presets: BTreeMap<String, Preset>,
+    #[serde(default)]
+    pub schedule: Vec<ScheduleEntry>,
+    /// Cleared when the config is edited away from the preset
+    #[serde(default)]
+    pub active_preset: Option<String>,
+    /// Preset applied with `ApplyMode::NextMessage` whose config has not
+    /// taken effect yet
+    #[serde(skip)]
+    pub pending_preset: Option<String>,
+}
+
+/// Layout of `config_presets.json`
+#[derive(Serialize, Deserialize)]
+struct PresetsFile<B> {
+    version: u32,
+    #[serde(flatten)]
+    book: B,
+}
+
+fn validate_name(name: &str) -> Result<String, PresetError> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err(PresetError::InvalidName(
+            "name must not be empty".to_string(),
+        ));
+    }
+    if name.chars().count() > MAX_NAME_LEN {
+        return Err(PresetError::InvalidName(format!(
+            "name must be at most {MAX_NAME_LEN} characters"
+        )));
+    }
+    if name.chars().any(|c| c == '/' || c.is_control()) {
+        return Err(PresetError::InvalidName(
+            "name must not contain '/' or control characters".to_string(),
+        ));
+    }
+    Ok(name.to_string())
+}
+
+impl PresetBook {
+    pub fn get(&self, name: &str) -> Result<&Preset, PresetError> {
+        self.presets
+            .get(name)
+            .ok_or_else(|| PresetError::NotFound(name.to_string()))
+    }
+
+    pub fn create(
+        &mut self,
+        name: &str,
+        config: TransformerConfig,
+        overwrite: bool,
+    ) -> Result<&Preset, PresetError> {
+        let name = validate_name(name)?;
+        let now = Utc::now();
+        let created_at = match self.presets.get(&name) {
+            Some(_) if !overwrite => return Err(PresetError::AlreadyExists(name)),
+            Some(existing) => existing.created_at,
+            None => now,
+        };
+        if overwrite && self.active_preset.as_deref() == Some(name.as_str()) {
+            self.active_preset = None;
+        }
+        self.presets.insert(
+            name.clone(),
+            Preset {
+                name: name.clone(),
+                config,
+                created_at,
+                updated_at: now,
+            },
+        );
+        Ok(&self.presets[&name])
+    }
+
+    pub fn duplicate(&mut self, from: &str, to: &str) -> Result<&Preset, PresetError> {
+        let config = self.get(from)?.config.clone();
+        self.create(to, config, false)
+    }
+
+    pub fn delete(&mut self, name: &str) -> Result<(), PresetError> {
+        self.get(name)?;
+        if self.schedule.iter().any(|entry| entry.preset == name) {
+            return Err(PresetError::Scheduled(name.to_string()));
+        }
+        self.presets.remove(name);
+        if self.active_preset.as_deref() == Some(name) {
+            self.active_preset = None;
+        }
+        if self.pending_preset.as_deref() == Some(name) {
+            self.pending_preset = None;
+        }
+        Ok(())
+    }
+
+    fn has_config(&self, name: Option<&str>, config: &TransformerConfig) -> bool {
+        name.and_then(|name| self.presets.get(name))
+            .is_some_and(|preset| preset.config == *config)
+    }
+
+    /// Keeps `active_preset` only while the config still matches it, and
+    /// drops a pending preset the change replaces.
+    /// Returns true if the active preset was cleared.
+    pub fn note_config_change(&mut self, config: &TransformerConfig) -> bool {
+        if !self.has_config(self.pending_preset.as_deref(), config) {
+            self.pending_preset = None;
+        }
+        if !self.has_config(self.active_preset.as_deref(), config) && self.active_preset.is_some() {
+            self.active_preset = None;
+            return true;
+        }
+        false
+    }
+
+    /// Makes the pending preset active once `config`, the config that just
+    /// took effect, is its config. Returns true if the active preset changed.
+    pub fn activate_pending(&mut self, config: &TransformerConfig) -> bool {
+        let Some(name) = self.pending_preset.take() else {
+            return false;
+        };
+        if !self.has_config(Some(&name), config) || self.active_preset.as_deref() == Some(&name) {
+            return false;
+        }
+        self.active_preset = Some(name);
+        true
+    }
+
+    pub fn add_schedule(
+        &mut self,
+        id: String,
+        preset: &str,
+        daily_at: Option<&str>,
+        at: Option<DateTime<Utc>>,
+    ) -> Result<&ScheduleEntry, PresetError> {
+        self.get(preset)?;
+        let daily_at = daily_at
+            .map(|time| {
+                NaiveTime::parse_from_str(time, "%H:%M")
+                    .or_else(|_| NaiveTime::parse_from_str(time, "%H:%M:%S"))
+                    .map_err(|_| {
+                        PresetError::InvalidSchedule(format!(
+                            "daily_at must be HH:MM, got {time:?}"
+                        ))
+                    })
+            })
+            .transpose()?;
+        match (daily_at, at) {
+            (Some(_), Some(_)) | (None, None) => {
+                return Err(PresetError::InvalidSchedule(
+                    "give exactly one of daily_at or at".to_string(),
+                ));
+            }
+            (None, Some(at)) if at <= Utc::now() => {
+                return Err(PresetError::InvalidSchedule(
+                    "at must be in the future".to_string(),
+                ));
+            }
+            _ => {}
+        }
+
+        self.schedule.push(ScheduleEntry {
+            id,
+            preset: preset.to_string(),
+            daily_at,
+            at,
+        });
+        Ok(self.schedule.last().expect("entry was just pushed"))
+    }
+
+    pub fn remove_schedule(&mut self, id: &str) -> Result<(), PresetError> {
+        let before = self.schedule.len();
+        self.schedule.retain(|entry| entry.id != id);
+        if self.schedule.len() == before {
+            return Err(PresetError::ScheduleNotFound(id.to_string()));
+        }
+        Ok(())
+    }
+
+    /// Returns the preset that should be applied for schedule entries firing
+    /// within `(since, now]` (the latest one wins) and drops fired one-offs.
+    /// One-offs missed while the service was down fire on the first check.
+    pub fn take_due(&mut self, since: DateTime<Local>, now: DateTime<Local>) -> Option<String> {
+        let due = self
+            .schedule
+            .iter()
+            .filter_map(|entry| Some((entry.due_within(since, now)?, entry.preset.clone())))
+            .max_by_key(|(fire, _)| *fire)
+            .map(|(_, preset)| preset);
+        self.schedule
+            .retain(|entry| entry.at.is_none_or(|at| at.with_timezone(&Local) > now));
+        due
+    }
+}
+
+pub fn load_presets(file_path: &str) -> (PresetBook, LoadStatus) {
+    let (file, status) =
+        storage::load_with_fallback::<PresetsFile<PresetBook>>(file_path, &PRESETS_SCHEMA);
+    let book = file.map(|file| file.book).unwrap_or_default();
+    if !book.presets.is_empty() {
+        println!(
+            "Loaded {} config presets from {}",
+            book.presets.len(),
+            file_path
+        );
+    }
+    (book, status)
+}
+
+pub fn save_presets(book: &PresetBook, file_path: &str) {
+    let file = PresetsFile {
+        version: PRESETS_SCHEMA.current_version(),
+        book,
+    };
+    match serde_json::to_string_pretty(&file) {
+        Ok(json_content) => match storage::save_with_snapshots(file_path, &json_content) {
+            Ok(_) => println!("Presets saved to {}", file_path),
+            Err(e) => eprintln!("Failed to write presets to {}: {}", file_path, e),
+        },
+        Err(e) => eprintln!("Failed to serialize presets: {}", e),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{Duration, TimeZone};
+
+    fn book() -> PresetBook {
+        let mut book = PresetBook::default();
+        for (name, tempo) in [("day", 400), ("night", 1000)] {
+            let config = TransformerConfig {
+                tempo_choices: vec![tempo],
+                ..TransformerConfig::default()
+            };
+            book.create(name, config, false).unwrap();
+        }
+        book
+    }
+
+    #[test]
+    fn test_take_due_picks_latest_entry_and_drops_one_offs() {
+        let mut book = book();
+        let now = Local
+            .with_ymd_and_hms(2026, 3, 1, 0, 5, 0)
+            .single()
+            .unwrap();
+        book.add_schedule("a".into(), "day", Some("23:59"), None)
+            .unwrap();
+        book.add_schedule("b".into(), "night", Some("00:01"), None)
+            .unwrap();
+        book.schedule.push(ScheduleEntry {
+            id: "c".into(),
+            preset: "day".into(),
+            daily_at: None,
+            at: Some((now - Duration::hours(5)).with_timezone(&Utc)),
+        });
+
+        // Both daily entries fall in a window spanning midnight; the later wins
+        let since = now - Duration::minutes(10);
+        assert_eq!(book.take_due(since, now), Some("night".to_string()));
+        // The missed one-off fired and is gone, the daily entries stay
+        let ids: Vec<&str> = book.schedule.iter().map(|e| e.id.as_str()).collect();
+        assert_eq!(ids, ["a", "b"]);
+        assert_eq!(book.take_due(now, now + Duration::minutes(1)), None);
+    }
+
+    #[test]
+    fn test_schedule_errors_and_pending_preset() {
+        let mut book = book();
+        let past = Utc::now() - Duration::hours(1);
+        for (preset, daily_at, at) in [
+            ("day", None, None),
+            ("day", Some("07:00"), Some(Utc::now() + Duration::hours(1))),
+            ("day", Some("7 am"), None),
+            ("day", None, Some(past)),
+        ] {
+            let error = book.add_schedule("x".into(), preset, daily_at, at);
+            assert!(matches!(error, Err(PresetError::InvalidSchedule(_))));
+        }
+        assert!(matches!(
+            book.add_schedule("x".into(), "dusk", Some("07:00"), None),
+            Err(PresetError::NotFound(_))
+        ));
+        assert!(matches!(
+            book.remove_schedule("x"),
+            Err(PresetError::ScheduleNotFound(_))
+        ));
+        book.add_schedule("x".into(), "day", Some("07:00"), None)
+            .unwrap();
+        assert!(matches!(book.delete("day"), Err(PresetError::Scheduled(_))));
+
+        // A queued preset only becomes active once its config runs
+        let night = book.get("night").unwrap().config.clone();
+        book.pending_preset = Some("night".to_string());
+        assert!(!book.activate_pending(&TransformerConfig::default()));
+        assert_eq!(book.active_preset, None);
+        book.pending_preset = Some("night".to_string());
+        assert!(book.activate_pending(&night));
+        assert_eq!(book.active_preset.as_deref(), Some("night"));
+    }
+}
@@ -0,0 +1,217 @@
+use parking_lot::Mutex;
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::net::IpAddr;
+use std::time::{Duration, Instant};
+
+/// Most buckets kept. Once reached, refilled buckets are dropped and, if
+/// that is not enough, the least recently used ones.
+const MAX_TRACKED_ADDRESSES: usize = 4096;
+
+/// Layout of `limits.toml`. A rate of 0 turns that limit off.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default)]
+pub struct LimitsConfig {
+    /// Writes per minute from one address: every request that changes
+    /// messages, the config, presets or the schedule, and logging in or out
+    pub writes_per_minute: u32,
+    /// Visitor submissions per hour from one address
+    pub submissions_per_hour: u32,
+    /// Most messages stored, pending and rejected ones included but not
+    /// those in the trash
+    pub max_messages: usize,
+    /// Most submissions waiting for moderation
+    pub max_pending: usize,
+    /// Largest JSON request body in bytes
+    pub max_body_bytes: u64,
+    /// Largest import upload in bytes
+    pub max_import_bytes: u64,
+    /// Days deleted messages stay in the trash before they are purged; 0
+    /// keeps them until purged by hand
+    pub trash_retention_days: u32,
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        LimitsConfig {
+            writes_per_minute: 60,
+            submissions_per_hour: 20,
+            max_messages: 10_000,
+            max_pending: 500,
+            max_body_bytes: 16 * 1024,
+            max_import_bytes: 2 * 1024 * 1024,
+            trash_retention_days: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LimitKind {
+    Write,
+    Submission,
+}
+
+/// A client over its rate and how long it has to wait
+#[derive(Debug)]
+pub struct RateLimited {
+    pub retry_after_secs: u64,
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token bucket per client address: up to `limit` requests at once, refilled
+/// evenly over `period`.
+pub struct RateLimiter {
+    capacity: f64,
+    refill_per_sec: f64,
+    buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn new(limit: u32, period: Duration) -> Self {
+        RateLimiter {
+            capacity: f64::from(limit),
+            refill_per_sec: f64::from(limit) / period.as_secs_f64(),
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token for `ip`, or returns how many seconds until one is free.
+    pub fn check(&self, ip: IpAddr) -> Result<(), u64> {
+        self.check_at(ip, Instant::now())
+    }
+
+    fn check_at(&self, ip: IpAddr, now: Instant) -> Result<(), u64> {
+        if self.capacity == 0.0 {
+            return Ok(());
+        }
+        let mut buckets = self.buckets.lock();
+        if buckets.len() >= MAX_TRACKED_ADDRESSES {
+            let (capacity, refill) = (self.capacity, self.refill_per_sec);
+            buckets.retain(|_, bucket| {
+                bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * refill < capacity
+            });
+        }
+        if buckets.len() >= MAX_TRACKED_ADDRESSES {
+            // Evicts down to three quarters so a flood of new addresses does
+            // not sort the map on every request
+            let mut ages: Vec<Instant> = buckets.values().map(|bucket| bucket.updated).collect();
+            let keep = MAX_TRACKED_ADDRESSES * 3 / 4;
+            let cutoff_index = ages.len() - keep;
+            let (_, cutoff, _) = ages.select_nth_unstable(cutoff_index);
+            let cutoff = *cutoff;
+            buckets.retain(|_, bucket| bucket.updated >= cutoff);
+        }
+
+        let bucket = buckets.entry(ip).or_insert(Bucket {
+            tokens: self.capacity,
+            updated: now,
+        });
+        let elapsed = now.duration_since(bucket.updated).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * self.refill_per_sec).min(self.capacity);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            Ok(())
+        } else {
+            Err(((1.0 - bucket.tokens) / self.refill_per_sec).ceil() as u64)
+        }
+    }
+}
+
+/// Limits from `limits.toml` with the rate limiters they configure.
+pub struct Limits {
+    pub config: LimitsConfig,
+    writes: RateLimiter,
+    submissions: RateLimiter,
+}
+
+impl Limits {
+    pub fn new(config: LimitsConfig) -> Self {
+        Limits {
+            writes: RateLimiter::new(config.writes_per_minute, Duration::from_secs(60)),
+            submissions: RateLimiter::new(config.submissions_per_hour, Duration::from_secs(3600)),
+            config,
+        }
+    }
+
+    /// Reads `file_path`; without the file the defaults apply.
+    pub fn load(file_path: &str) -> Result<Self, String> {
+        let config = match fs::read_to_string(file_path) {
+            Ok(content) => toml::from_str(&content)
+                .map_err(|e| format!("Invalid limits file {file_path}: {e}"))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => LimitsConfig::default(),
+            Err(e) => return Err(format!("Failed to read {file_path}: {e}")),
+        };
+        Ok(Self::new(config))
+    }
+
+    pub fn check(&self, kind: LimitKind, ip: IpAddr) -> Result<(), RateLimited> {
+        let limiter = match kind {
+            LimitKind::Write => &self.writes,
+            LimitKind::Submission => &self.submissions,
+        };
+        limiter
+            .check(ip)
+            .map_err(|retry_after_secs| RateLimited { retry_after_secs })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bucket_allows_burst_then_refills() {
+        let limiter = RateLimiter::new(3, Duration::from_secs(60));
+        let client: IpAddr = "192.0.2.1".parse().unwrap();
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+        let start = Instant::now();
+
+        for _ in 0..3 {
+            assert!(limiter.check_at(client, start).is_ok());
+        }
+        assert_eq!(limiter.check_at(client, start), Err(20));
+        assert!(limiter.check_at(other, start).is_ok());
+
+        // One token comes back every 20 seconds
+        assert!(
+            limiter
+                .check_at(client, start + Duration::from_secs(20))
+                .is_ok()
+        );
+        assert!(
+            limiter
+                .check_at(client, start + Duration::from_secs(21))
+                .is_err()
+        );
+
+        let unlimited = RateLimiter::new(0, Duration::from_secs(60));
+        for _ in 0..100 {
+            assert!(unlimited.check_at(client, start).is_ok());
+        }
+    }
+
+    #[test]
+    fn test_tracked_addresses_stay_bounded() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(3600));
+        let start = Instant::now();
+        let address = |i: usize| IpAddr::from([10, 0, (i >> 8) as u8, i as u8]);
+
+        // Every bucket is drained, so none of them has refilled
+        for i in 0..MAX_TRACKED_ADDRESSES * 2 {
+            let now = start + Duration::from_millis(i as u64);
+            assert!(limiter.check_at(address(i), now).is_ok());
+            assert!(limiter.buckets.lock().len() <= MAX_TRACKED_ADDRESSES);
+        }
+        // The most recent clients are still limited
+        let last = MAX_TRACKED_ADDRESSES * 2 - 1;
+        let now = start + Duration::from_millis(last as u64);
+        assert!(limiter.check_at(address(last), now).is_err());
+    }
+}
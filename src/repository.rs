@@ -0,0 +1,465 @@
+use crate::Message;
+use crate::migration::MESSAGES_SCHEMA;
+use crate::storage::{self, LoadStatus};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+#[derive(Debug)]
+pub enum RepositoryError {
+    Io(std::io::Error),
+    Serialize(serde_json::Error),
+    #[cfg(feature = "sqlite")]
+    Sqlite(rusqlite::Error),
+}
+
+impl std::fmt::Display for RepositoryError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RepositoryError::Io(e) => write!(f, "IO error: {e}"),
+            RepositoryError::Serialize(e) => write!(f, "Serialization error: {e}"),
+            #[cfg(feature = "sqlite")]
+            RepositoryError::Sqlite(e) => write!(f, "SQLite error: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for RepositoryError {}
+
+impl From<std::io::Error> for RepositoryError {
+    fn from(err: std::io::Error) -> Self {
+        RepositoryError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for RepositoryError {
+    fn from(err: serde_json::Error) -> Self {
+        RepositoryError::Serialize(err)
+    }
+}
+
+#[cfg(feature = "sqlite")]
+impl From<rusqlite::Error> for RepositoryError {
+    fn from(err: rusqlite::Error) -> Self {
+        RepositoryError::Sqlite(err)
+    }
+}
+
+/// Persistence for the message pool. The in-memory `MessageStore` stays the
+/// working copy; every mutation is also reported here so backends that can
+/// write incrementally do so immediately.
+pub trait MessageRepository: Send + Sync {
+    fn name(&self) -> &'static str;
+
+    fn load_all(&self) -> (HashMap<String, Message>, LoadStatus);
+
+    fn upsert(&self, message: &Message) -> Result<(), RepositoryError>;
+
+    fn delete(&self, id: &str) -> Result<(), RepositoryError>;
+
+    /// Replaces the stored pool with `messages` in one write.
+    fn replace_all(&self, messages: &HashMap<String, Message>) -> Result<(), RepositoryError>;
+
+    /// Called by the auto-save scheduler and on shutdown. Backends that commit
+    /// every mutation only need to catch up after failed writes.
+    fn flush(&self, messages: &HashMap<String, Message>) -> Result<(), RepositoryError>;
+}
+
+/// Logs a failed repository write; the in-memory store keeps the change and
+/// the next flush retries it.
+pub fn log_write_error(result: Result<(), RepositoryError>) {
+    if let Err(e) = result {
+        eprintln!("Failed to persist message change: {}", e);
+    }
+}
+
+/// Layout of `messages.json`
+#[derive(Serialize, Deserialize)]
+struct MessagesFile<M> {
+    version: u32,
+    messages: M,
+}
+
+pub struct JsonMessageRepository {
+    file_path: String,
+    /// Set by mutations not yet written to the file
+    dirty: AtomicBool,
+}
+
+impl JsonMessageRepository {
+    pub fn new(file_path: &str) -> Self {
+        JsonMessageRepository {
+            file_path: file_path.to_string(),
+            dirty: AtomicBool::new(false),
+        }
+    }
+
+    fn save(&self, messages: &HashMap<String, Message>) -> Result<(), RepositoryError> {
+        // Cleared first so a mutation racing with the write marks it dirty again
+        self.dirty.store(false, Ordering::SeqCst);
+        let result = save_messages_to_file(messages, &self.file_path);
+        if result.is_err() {
+            self.dirty.store(true, Ordering::SeqCst);
+        }
+        result
+    }
+}
+
+impl MessageRepository for JsonMessageRepository {
+    fn name(&self) -> &'static str {
+        "json"
+    }
+
+    fn load_all(&self) -> (HashMap<String, Message>, LoadStatus) {
+        load_messages_from_file(&self.file_path)
+    }
+
+    // The JSON file is rewritten as a whole by `flush`
+    fn upsert(&self, _message: &Message) -> Result<(), RepositoryError> {
+        self.dirty.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn delete(&self, _id: &str) -> Result<(), RepositoryError> {
+        self.dirty.store(true, Ordering::SeqCst);
+        Ok(())
+    }
+
+    fn replace_all(&self, messages: &HashMap<String, Message>) -> Result<(), RepositoryError> {
+        self.save(messages)
+    }
+
+    fn flush(&self, messages: &HashMap<String, Message>) -> Result<(), RepositoryError> {
+        if !self.dirty.load(Ordering::SeqCst) {
+            return Ok(());
+        }
+        self.save(messages)
+    }
+}
+
+/// Parses the contents of `messages.json`, e.g. after it was edited by hand.
+pub fn parse_messages(content: &str) -> Result<HashMap<String, Message>, String> {
+    storage::parse_versioned::<MessagesFile<HashMap<String, Message>>>(content, &MESSAGES_SCHEMA)
+        .map(|(file, _)| file.messages)
+}
+
+pub fn load_messages_from_file(file_path: &str) -> (HashMap<String, Message>, LoadStatus) {
+    let (file, status) = storage::load_with_fallback::<MessagesFile<HashMap<String, Message>>>(
+        file_path,
+        &MESSAGES_SCHEMA,
+    );
+    match &status {
+        LoadStatus::Missing => println!(
+            "Messages file {} not found, starting with empty message store",
+            file_path
+        ),
+        LoadStatus::Empty => println!(
+            "Messages file {} is empty, starting with empty message store",
+            file_path
+        ),
+        LoadStatus::Failed { .. } => println!("Starting with empty message store"),
+        LoadStatus::Loaded | LoadStatus::Migrated { .. } | LoadStatus::Recovered { .. } => {}
+    }
+    let messages = file.map(|file| file.messages).unwrap_or_default();
+    if !messages.is_empty() {
+        println!("Loaded {} messages from {}", messages.len(), file_path);
+    }
+    (messages, status)
+}
+
+fn save_messages_to_file(
+    messages: &HashMap<String, Message>,
+    file_path: &str,
+) -> Result<(), RepositoryError> {
+    // Don't save if messages is empty and file already exists with content
+    if messages.is_empty()
+        && Path::new(file_path).exists()
+        && let Ok(content) = fs::read_to_string(file_path)
+        && !content.trim().is_empty()
+        && content.trim() != "{}"
+        && !is_empty_messages_file(&content)
+    {
+        println!("WARNING: Skipping save of empty message store - file has existing data");
+        return Ok(());
+    }
+
+    let json_content = serde_json::to_string_pretty(&MessagesFile {
+        version: MESSAGES_SCHEMA.current_version(),
+        messages,
+    })?;
+    storage::save_with_snapshots(file_path, &json_content)?;
+    println!(
+        "Messages saved to {} ({} messages)",
+        file_path,
+        messages.len()
+    );
+    Ok(())
+}
+
+fn is_empty_messages_file(content: &str) -> bool {
+    serde_json::from_str::<MessagesFile<HashMap<String, serde_json::Value>>>(content)
+        .is_ok_and(|file| file.messages.is_empty())
+}
+
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteMessageRepository;
+
+#[cfg(feature = "sqlite")]
+mod sqlite {
+    use super::{MessageRepository, RepositoryError, load_messages_from_file};
+    use crate::Message;
+    use crate::storage::LoadStatus;
+    use parking_lot::Mutex;
+    use rusqlite::{Connection, params};
+    use std::collections::HashMap;
+    use std::path::Path;
+    use std::sync::atomic::{AtomicBool, Ordering};
+
+    const SCHEMA: &str = "
+        CREATE TABLE IF NOT EXISTS messages (
+            id TEXT PRIMARY KEY,
+            text TEXT NOT NULL,
+            created_at TEXT NOT NULL,
+            last_sent TEXT,
+            send_count INTEGER NOT NULL,
+            data TEXT NOT NULL
+        );
+    ";
+
+    /// Stores every message as a row, committed on each mutation. Only
+    /// messages live here; play history stays in `play_history.jsonl` with
+    /// either backend. A few fields get their own columns for inspecting the
+    /// database by hand; the full record is kept as JSON in `data` so new
+    /// `Message` fields need no schema change.
+    pub struct SqliteMessageRepository {
+        connection: Mutex<Connection>,
+        // Set when a write failed, so the next flush rewrites everything
+        needs_resync: AtomicBool,
+    }
+
+    impl SqliteMessageRepository {
+        pub fn open(database_path: &str) -> Result<Self, RepositoryError> {
+            let connection = Connection::open(database_path)?;
+            connection.pragma_update(None, "journal_mode", "WAL")?;
+            connection.pragma_update(None, "synchronous", "FULL")?;
+            connection.execute_batch(SCHEMA)?;
+            println!("Opened SQLite message store {}", database_path);
+            Ok(SqliteMessageRepository {
+                connection: Mutex::new(connection),
+                needs_resync: AtomicBool::new(false),
+            })
+        }
+
+        /// Copies `messages.json` into an empty database. Existing rows are
+        /// never overwritten, so this is safe to run on every start.
+        pub fn import_json_if_empty(&self, json_path: &str) -> Result<usize, RepositoryError> {
+            let count: i64 =
+                self.connection
+                    .lock()
+                    .query_row("SELECT COUNT(*) FROM messages", [], |row| row.get(0))?;
+            if count > 0 || !Path::new(json_path).exists() {
+                return Ok(0);
+            }
+
+            let (messages, _) = load_messages_from_file(json_path);
+            self.replace_all(&messages)?;
+            println!(
+                "Imported {} messages from {} into SQLite",
+                messages.len(),
+                json_path
+            );
+            Ok(messages.len())
+        }
+
+        fn write_row(connection: &Connection, message: &Message) -> Result<(), RepositoryError> {
+            connection.execute(
+                "INSERT INTO messages (id, text, created_at, last_sent, send_count, data)
+                 VALUES (?1, ?2, ?3, ?4, ?5, ?6)
+                 ON CONFLICT(id) DO UPDATE SET
+                    text = excluded.text,
+                    created_at = excluded.created_at,
+                    last_sent = excluded.last_sent,
+                    send_count = excluded.send_count,
+                    data = excluded.data",
+                params![
+                    message.id,
+                    message.text,
+                    message.created_at.to_rfc3339(),
+                    message.last_sent.map(|t| t.to_rfc3339()),
+                    message.send_count,
+                    serde_json::to_string(message)?,
+                ],
+            )?;
+            Ok(())
+        }
+
+        fn track<T>(&self, result: Result<T, RepositoryError>) -> Result<T, RepositoryError> {
+            if result.is_err() {
+                self.needs_resync.store(true, Ordering::SeqCst);
+            }
+            result
+        }
+    }
+
+    impl MessageRepository for SqliteMessageRepository {
+        fn name(&self) -> &'static str {
+            "sqlite"
+        }
+
+        fn load_all(&self) -> (HashMap<String, Message>, LoadStatus) {
+            let connection = self.connection.lock();
+            let rows = connection
+                .prepare("SELECT data FROM messages ORDER BY created_at")
+                .and_then(|mut statement| {
+                    statement
+                        .query_map([], |row| row.get::<_, String>(0))?
+                        .collect::<Result<Vec<String>, _>>()
+                });
+
+            match rows {
+                Ok(rows) => {
+                    let mut messages = HashMap::new();
+                    for data in rows {
+                        match serde_json::from_str::<Message>(&data) {
+                            Ok(message) => {
+                                messages.insert(message.id.clone(), message);
+                            }
+                            Err(e) => eprintln!("Skipping unreadable message row: {}", e),
+                        }
+                    }
+                    println!("Loaded {} messages from SQLite", messages.len());
+                    let status = if messages.is_empty() {
+                        LoadStatus::Empty
+                    } else {
+                        LoadStatus::Loaded
+                    };
+                    (messages, status)
+                }
+                Err(e) => {
+                    eprintln!("Failed to load messages from SQLite: {}", e);
+                    (
+                        HashMap::new(),
+                        LoadStatus::Failed {
+                            error: e.to_string(),
+                        },
+                    )
+                }
+            }
+        }
+
+        fn upsert(&self, message: &Message) -> Result<(), RepositoryError> {
+            let result = Self::write_row(&self.connection.lock(), message);
+            self.track(result)
+        }
+
+        fn delete(&self, id: &str) -> Result<(), RepositoryError> {
+            let result = self
+                .connection
+                .lock()
+                .execute("DELETE FROM messages WHERE id = ?1", params![id])
+                .map(|_| ())
+                .map_err(RepositoryError::from);
+            self.track(result)
+        }
+
+        fn replace_all(&self, messages: &HashMap<String, Message>) -> Result<(), RepositoryError> {
+            let mut connection = self.connection.lock();
+            let result = (|| {
+                let transaction = connection.transaction()?;
+                transaction.execute("DELETE FROM messages", [])?;
+                for message in messages.values() {
+                    Self::write_row(&transaction, message)?;
+                }
+                transaction.commit()?;
+                Ok(())
+            })();
+            if result.is_ok() {
+                self.needs_resync.store(false, Ordering::SeqCst);
+            }
+            self.track(result)
+        }
+
+        fn flush(&self, messages: &HashMap<String, Message>) -> Result<(), RepositoryError> {
+            if self.needs_resync.load(Ordering::SeqCst) {
+                println!("Re-syncing SQLite message store after a failed write");
+                return self.replace_all(messages);
+            }
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn test_message(id: &str, text: &str) -> Message {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "text": text,
+            "created_at": "2026-01-01T00:00:00Z",
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_json_round_trip_and_flush_only_when_dirty() {
+        let dir = storage::test_dir("json-repository");
+        let path = dir.join("messages.json").display().to_string();
+        let repository = JsonMessageRepository::new(&path);
+        let mut messages: HashMap<String, Message> = ["a", "b"]
+            .map(|id| (id.to_string(), test_message(id, "hello")))
+            .into();
+        repository.replace_all(&messages).unwrap();
+
+        let (loaded, status) = repository.load_all();
+        assert!(matches!(status, LoadStatus::Loaded));
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded["a"].text, "hello");
+
+        // Nothing reported since the last save, so flush leaves the file alone
+        messages.remove("b");
+        repository.flush(&messages).unwrap();
+        assert_eq!(repository.load_all().0.len(), 2);
+
+        repository.delete("b").unwrap();
+        repository.flush(&messages).unwrap();
+        let (loaded, _) = repository.load_all();
+        assert_eq!(loaded.keys().collect::<Vec<_>>(), ["a"]);
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn test_sqlite_round_trip_and_json_import() {
+        let dir = storage::test_dir("sqlite-repository");
+        let json_path = dir.join("messages.json").display().to_string();
+        let messages: HashMap<String, Message> = ["a", "b"]
+            .map(|id| (id.to_string(), test_message(id, "hello")))
+            .into();
+        JsonMessageRepository::new(&json_path)
+            .replace_all(&messages)
+            .unwrap();
+
+        let db_path = dir.join("messages.db").display().to_string();
+        let repository = SqliteMessageRepository::open(&db_path).unwrap();
+        assert_eq!(repository.import_json_if_empty(&json_path).unwrap(), 2);
+        // Rows already exist, so a second start imports nothing
+        assert_eq!(repository.import_json_if_empty(&json_path).unwrap(), 0);
+
+        let mut edited = test_message("a", "edited");
+        edited.send_count = 3;
+        repository.upsert(&edited).unwrap();
+        repository.upsert(&test_message("c", "new")).unwrap();
+        repository.delete("b").unwrap();
+
+        let (loaded, status) = SqliteMessageRepository::open(&db_path).unwrap().load_all();
+        assert!(matches!(status, LoadStatus::Loaded));
+        let mut ids: Vec<&String> = loaded.keys().collect();
+        ids.sort();
+        assert_eq!(ids, ["a", "c"]);
+        assert_eq!(loaded["a"].text, "edited");
+        assert_eq!(loaded["a"].send_count, 3);
+    }
+}
@@ -51,6 +51,7 @@ impl SerialSender {
         Ok(Self { port })
     }
 
+    #[allow(dead_code)]
     pub fn list_ports() -> Result<Vec<String>, SerialError> {
         let ports = serialport::available_ports()?;
         let port_names: Vec<String> = ports
@@ -0,0 +1,447 @@
+use crate::migration::Schema;
+use chrono::Utc;
+use lazy_static::lazy_static;
+use parking_lot::Mutex;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Number of previous versions kept next to each persisted file as `<file>.1`
+/// (newest) to `<file>.N` (oldest).
+pub const SNAPSHOT_COUNT: usize = 5;
+
+lazy_static! {
+    /// Content hash of watched files as this process last read or wrote them
+    static ref SYNCED: Mutex<HashMap<String, u64>> = Mutex::new(HashMap::new());
+    /// One lock per saved file, held from the external-change check through
+    /// snapshot rotation to the final rename
+    static ref SAVE_LOCKS: Mutex<HashMap<PathBuf, Arc<Mutex<()>>>> = Mutex::new(HashMap::new());
+}
+
+/// Numbers temporary files, so concurrent writes never share one
+static TMP_COUNTER: AtomicU64 = AtomicU64::new(0);
+
+fn save_lock(path: &Path) -> Arc<Mutex<()>> {
+    SAVE_LOCKS
+        .lock()
+        .entry(path.to_path_buf())
+        .or_default()
+        .clone()
+}
+
+/// Outcome of loading a persisted file, reported through `/api/status`.
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum LoadStatus {
+    Loaded,
+    /// The file had an older schema version; it was upgraded and the
+    /// original kept as `backup`
+    Migrated {
+        from_version: u32,
+        to_version: u32,
+        backup: String,
+    },
+    Missing,
+    Empty,
+    /// The file was unreadable and the newest valid snapshot was used instead
+    Recovered {
+        snapshot: String,
+        error: String,
+    },
+    /// Neither the file nor any snapshot could be parsed
+    Failed {
+        error: String,
+    },
+}
+
+impl LoadStatus {
+    pub fn is_degraded(&self) -> bool {
+        matches!(
+            self,
+            LoadStatus::Recovered { .. } | LoadStatus::Failed { .. }
+        )
+    }
+}
+
+fn snapshot_path(path: &Path, index: usize) -> PathBuf {
+    let mut name = path.as_os_str().to_owned();
+    name.push(format!(".{index}"));
+    PathBuf::from(name)
+}
+
+/// Writes `contents` to a temporary file, syncs it and renames it over `path`,
+/// so a power cut leaves either the old or the new file, never a torn one.
+/// Each call writes its own temporary file, so concurrent writers cannot
+/// truncate each other's.
+pub fn write_atomic(path: &Path, contents: &[u8]) -> io::Result<()> {
+    let mut tmp_name = path.as_os_str().to_owned();
+    tmp_name.push(format!(
+        ".tmp-{}-{}",
+        std::process::id(),
+        TMP_COUNTER.fetch_add(1, Ordering::Relaxed)
+    ));
+    let tmp_path = PathBuf::from(tmp_name);
+
+    let written = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&tmp_path)
+        .and_then(|mut file| {
+            file.write_all(contents)?;
+            file.sync_all()
+        })
+        .and_then(|_| fs::rename(&tmp_path, path));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(e);
+    }
+
+    // Persist the rename itself
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    File::open(dir)?.sync_all()
+}
+
+fn rotate_snapshots(path: &Path, current: &[u8]) -> io::Result<()> {
+    for index in (1..SNAPSHOT_COUNT).rev() {
+        let from = snapshot_path(path, index);
+        if from.exists() {
+            fs::rename(&from, snapshot_path(path, index + 1))?;
+        }
+    }
+    write_atomic(&snapshot_path(path, 1), current)
+}
+
+pub fn content_hash(contents: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    contents.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// Records `contents` as the state of `path` this process is in sync with.
+/// Once a path is tracked, `save_with_snapshots` refuses to overwrite changes
+/// made by other programs until they have been reloaded and marked synced.
+pub fn mark_synced(path: &str, contents: &[u8]) {
+    SYNCED
+        .lock()
+        .insert(path.to_string(), content_hash(contents));
+}
+
+pub fn synced_hash(path: &str) -> Option<u64> {
+    SYNCED.lock().get(path).copied()
+}
+
+/// Atomically replaces `path`, first moving its previous contents into the
+/// snapshot rotation when they differ from the new ones. Saves of the same
+/// file from different threads run one at a time.
+pub fn save_with_snapshots(path: &str, contents: &str) -> io::Result<()> {
+    let file_path = Path::new(path);
+    let lock = save_lock(file_path);
+    let _guard = lock.lock();
+    let synced = synced_hash(path);
+    if let Ok(current) = fs::read(file_path) {
+        if synced.is_some_and(|hash| hash != content_hash(&current)) {
+            return Err(io::Error::other(format!(
+                "{path} was changed by another program and has not been reloaded yet"
+            )));
+        }
+        if current != contents.as_bytes()
+            && !current.is_empty()
+            && let Err(e) = rotate_snapshots(file_path, &current)
+        {
+            eprintln!("Failed to rotate snapshots of {}: {}", path, e);
+        }
+    }
+    write_atomic(file_path, contents.as_bytes())?;
+    if synced.is_some() {
+        mark_synced(path, contents.as_bytes());
+    }
+    Ok(())
+}
+
+/// Parses `content`, first running the schema migrations. Returns the value
+/// and the schema version the content was written with.
+pub fn parse_versioned<T: DeserializeOwned>(
+    content: &str,
+    schema: &Schema,
+) -> Result<(T, u32), String> {
+    let value = serde_json::from_str(content).map_err(|e| e.to_string())?;
+    let (value, found) = schema.migrate(value)?;
+    let parsed = serde_json::from_value(value).map_err(|e| e.to_string())?;
+    Ok((parsed, found))
+}
+
+/// Keeps the pre-migration file as `<file>.v<version>.bak` and rewrites
+/// `path` in the current schema. Returns the backup path.
+fn upgrade_file<T: Serialize>(path: &str, from_version: u32, value: &T) -> io::Result<String> {
+    let mut backup = format!("{}.v{}.bak", path, from_version);
+    if Path::new(&backup).exists() {
+        backup = format!(
+            "{}.v{}-{}.bak",
+            path,
+            from_version,
+            Utc::now().format("%Y%m%dT%H%M%S")
+        );
+    }
+    fs::copy(path, &backup)?;
+
+    let contents = serde_json::to_string_pretty(value)?;
+    write_atomic(Path::new(path), contents.as_bytes())?;
+    Ok(backup)
+}
+
+/// Loads `path`, upgrading older schema versions, and falls back to the newest
+/// snapshot that parses when the file itself is unreadable, or empty while
+/// snapshots exist. The broken file is kept as `<file>.corrupt-<time>`.
+pub fn load_with_fallback<T: DeserializeOwned + Serialize>(
+    path: &str,
+    schema: &Schema,
+) -> (Option<T>, LoadStatus) {
+    let file_path = Path::new(path);
+    if !file_path.exists() {
+        return (None, LoadStatus::Missing);
+    }
+
+    let error = match fs::read_to_string(file_path) {
+        // A torn or truncated write leaves an empty file behind, so it only
+        // counts as a fresh start when there is nothing to recover from
+        Ok(content) if content.trim().is_empty() => {
+            if !snapshot_path(file_path, 1).exists() {
+                return (None, LoadStatus::Empty);
+            }
+            format!("{} is empty", path)
+        }
+        Ok(content) => match parse_versioned::<T>(&content, schema) {
+            Ok((value, found)) if found < schema.current_version() => {
+                let to_version = schema.current_version();
+                return match upgrade_file(path, found, &value) {
+                    Ok(backup) => {
+                        println!(
+                            "Upgraded {} from schema v{} to v{} (original kept as {})",
+                            path, found, to_version, backup
+                        );
+                        (
+                            Some(value),
+                            LoadStatus::Migrated {
+                                from_version: found,
+                                to_version,
+                                backup,
+                            },
+                        )
+                    }
+                    Err(e) => {
+                        // The original stays on disk until the next save rotates it into a snapshot
+                        eprintln!("Failed to back up and upgrade {}: {}", path, e);
+                        (Some(value), LoadStatus::Loaded)
+                    }
+                };
+            }
+            Ok((value, _)) => return (Some(value), LoadStatus::Loaded),
+            Err(e) => format!("Failed to parse {}: {}", path, e),
+        },
+        Err(e) => format!("Failed to read {}: {}", path, e),
+    };
+    eprintln!("{}", error);
+
+    let corrupt_path = format!("{}.corrupt-{}", path, Utc::now().format("%Y%m%dT%H%M%S"));
+    match fs::copy(file_path, &corrupt_path) {
+        Ok(_) => eprintln!("Kept unreadable file as {}", corrupt_path),
+        Err(e) => eprintln!("Failed to keep unreadable file {}: {}", path, e),
+    }
+
+    for index in 1..=SNAPSHOT_COUNT {
+        let snapshot = snapshot_path(file_path, index);
+        let Ok(content) = fs::read_to_string(&snapshot) else {
+            continue;
+        };
+        match parse_versioned::<T>(&content, schema) {
+            Ok((value, _)) => {
+                eprintln!(
+                    "!!! RECOVERED {} FROM SNAPSHOT {} - recent changes may be lost !!!",
+                    path,
+                    snapshot.display()
+                );
+                return (
+                    Some(value),
+                    LoadStatus::Recovered {
+                        snapshot: snapshot.display().to_string(),
+                        error,
+                    },
+                );
+            }
+            Err(e) => eprintln!("Snapshot {} is also unreadable: {}", snapshot.display(), e),
+        }
+    }
+
+    eprintln!("!!! NO VALID SNAPSHOT FOR {} !!!", path);
+    (None, LoadStatus::Failed { error })
+}
+
+/// Appends `value` as one JSON line and syncs it. Callers serialize appends
+/// to the same file.
+pub fn append_json_line<T: Serialize>(path: &str, value: &T) -> io::Result<()> {
+    let mut line = serde_json::to_string(value)?;
+    line.push('\n');
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .read(true)
+        .append(true)
+        .open(path)?;
+    // Start on a fresh line if the previous write was cut short
+    if file.metadata()?.len() > 0 {
+        let mut last = [0u8; 1];
+        file.seek(SeekFrom::End(-1))?;
+        file.read_exact(&mut last)?;
+        if last[0] != b'\n' {
+            line.insert(0, '\n');
+        }
+    }
+    file.write_all(line.as_bytes())?;
+    file.sync_data()
+}
+
+/// Reads a file written with `append_json_line`. A missing file is empty;
+/// unreadable lines, such as one torn by a power cut, are skipped.
+pub fn read_json_lines<T: DeserializeOwned>(path: &str) -> io::Result<Vec<T>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+
+    let mut values = Vec::new();
+    for (index, line) in content.lines().enumerate() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        match serde_json::from_str(line) {
+            Ok(value) => values.push(value),
+            Err(e) => eprintln!("Skipping unreadable line {} of {}: {}", index + 1, path, e),
+        }
+    }
+    Ok(values)
+}
+
+/// A fresh directory for tests that touch the file system
+#[cfg(test)]
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("morse-{}-{}", name, uuid::Uuid::new_v4()));
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::migration::CONFIG_SCHEMA;
+    use serde_json::{Value, json};
+
+    fn versioned(n: u32) -> String {
+        json!({ "version": CONFIG_SCHEMA.current_version(), "n": n }).to_string()
+    }
+
+    #[test]
+    fn test_save_rotates_snapshots_up_to_the_cap() {
+        let dir = test_dir("snapshots");
+        let path = dir.join("file.json");
+        let path_str = path.display().to_string();
+
+        write_atomic(&path, b"first").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"first");
+
+        for n in 0..SNAPSHOT_COUNT + 2 {
+            save_with_snapshots(&path_str, &versioned(n as u32)).unwrap();
+        }
+        // An unchanged save does not push another snapshot
+        save_with_snapshots(&path_str, &versioned(SNAPSHOT_COUNT as u32 + 1)).unwrap();
+
+        for index in 1..=SNAPSHOT_COUNT {
+            let expected = versioned((SNAPSHOT_COUNT + 1 - index) as u32);
+            assert_eq!(
+                fs::read_to_string(snapshot_path(&path, index)).unwrap(),
+                expected
+            );
+        }
+        assert!(!snapshot_path(&path, SNAPSHOT_COUNT + 1).exists());
+    }
+
+    #[test]
+    fn test_concurrent_saves_never_leave_a_torn_file() {
+        let dir = test_dir("concurrent");
+        let path = dir.join("file.json").display().to_string();
+        let threads: Vec<_> = (0..8)
+            .map(|thread| {
+                let path = path.clone();
+                std::thread::spawn(move || {
+                    for round in 0..25 {
+                        // Large enough that a write takes more than one syscall
+                        let padding = "x".repeat(64 * 1024);
+                        let contents =
+                            json!({ "thread": thread, "round": round, "padding": padding });
+                        save_with_snapshots(&path, &contents.to_string()).unwrap();
+                    }
+                })
+            })
+            .collect();
+        for thread in threads {
+            thread.join().unwrap();
+        }
+
+        let mut files = vec![PathBuf::from(&path)];
+        files.extend((1..=SNAPSHOT_COUNT).map(|i| snapshot_path(Path::new(&path), i)));
+        for file in files {
+            let content = fs::read_to_string(&file).unwrap();
+            assert!(serde_json::from_str::<Value>(&content).is_ok(), "{file:?}");
+        }
+        let leftovers = fs::read_dir(&dir)
+            .unwrap()
+            .filter(|entry| {
+                entry
+                    .as_ref()
+                    .unwrap()
+                    .file_name()
+                    .to_string_lossy()
+                    .contains(".tmp")
+            })
+            .count();
+        assert_eq!(leftovers, 0);
+    }
+
+    #[test]
+    fn test_corrupt_or_empty_file_falls_back_to_snapshot() {
+        let dir = test_dir("fallback");
+        let path = dir.join("file.json");
+        let path_str = path.display().to_string();
+
+        fs::write(&path, "").unwrap();
+        let (value, status) = load_with_fallback::<Value>(&path_str, &CONFIG_SCHEMA);
+        assert!(value.is_none());
+        assert!(matches!(status, LoadStatus::Empty));
+
+        save_with_snapshots(&path_str, &versioned(1)).unwrap();
+        save_with_snapshots(&path_str, &versioned(2)).unwrap();
+
+        for broken in ["{ torn", ""] {
+            fs::write(&path, broken).unwrap();
+            let (value, status) = load_with_fallback::<Value>(&path_str, &CONFIG_SCHEMA);
+            assert_eq!(value.unwrap()["n"], 1, "{broken:?}");
+            assert!(matches!(status, LoadStatus::Recovered { .. }));
+        }
+
+        fs::write(snapshot_path(&path, 1), "{ torn").unwrap();
+        fs::write(&path, "").unwrap();
+        let (value, status) = load_with_fallback::<Value>(&path_str, &CONFIG_SCHEMA);
+        assert!(value.is_none());
+        assert!(matches!(status, LoadStatus::Failed { .. }));
+    }
+}